@@ -1,22 +1,101 @@
+// `#[component]` drops the attributes of the hidden `__Name` function it
+// generates, so the lints can't be allowed on each component
+#![allow(clippy::must_use_candidate)]
+
+use std::cell::Cell;
+
 use wasm_bindgen::JsCast;
-use web_sys::{window, HtmlAnchorElement, MouseEvent};
+use web_sys::{Element, Event, HtmlAnchorElement, MouseEvent};
 
+use leptos::ev::{click, scroll, Custom};
 use leptos::html::Div;
-use leptos::{
-    component, create_effect, expect_context, provide_context, view, AttributeValue, Children,
-    IntoView, Memo, NodeRef, RwSignal, SignalGet, SignalSet,
-};
-use leptos_router::A as ARouter;
-use leptos_use::{use_active_element, use_element_visibility};
+use leptos::logging::debug_warn;
+use leptos::prelude::*;
+use leptos_router::components::A as ARouter;
+use leptos_router::hooks::use_location;
+use leptos_router::location::BrowserUrl;
+use leptos_use::{use_active_element, use_element_visibility, use_event_listener};
 
+use crate::config::META_NAME;
+use crate::engagement;
+use crate::event::url_path;
 use crate::experiments::use_experiment;
-use crate::Plausible;
+use crate::{is_browser, EventBuilder, Plausible, PlausibleConfig};
 
 /// Sets the plausible context. It should usually be somewhere near the
 /// root of your application (Similar to the `<Router />` component).
-pub fn provide_plausible_context() {
-    let tracking = Plausible::new_private("test", "https://frumentarii.8vi.cat");
-    provide_context(tracking);
+///
+/// See [`PlausibleConfig`] for the available settings.
+pub fn provide_plausible_context(config: PlausibleConfig) {
+    provide_plausible_context_with(Plausible::from(config));
+}
+
+/// Like [`provide_plausible_context`] for an already built [`Plausible`],
+/// e.g. one with another [`Transport`](crate::transport::Transport):
+///
+/// ```
+/// # use leptos_plausible::components::provide_plausible_context_with;
+/// # use leptos_plausible::transport::RecordingTransport;
+/// # use leptos_plausible::Plausible;
+/// # leptos::prelude::Owner::new().with(|| {
+/// provide_plausible_context_with(
+///     Plausible::new("your_domain").with_transport(RecordingTransport::default()),
+/// );
+/// # });
+/// ```
+///
+/// Use it instead of `provide_context`, which would skip the opt-out signal,
+/// the dispatcher and the queue.
+pub fn provide_plausible_context_with(plausible: Plausible) {
+    // Retries bypass the dispatcher, they were already rate limited
+    if let Some(queue) = plausible.config().queue.clone().filter(|_| is_browser()) {
+        // Retries skip the checks of each event, they may have been queued
+        // before tracking was disabled or refused
+        let allowed = plausible.config().enabled
+            && plausible.privacy_signal().is_none()
+            && !plausible.is_opted_out();
+        if allowed {
+            crate::queue::start(plausible.transport(), queue);
+        } else {
+            crate::queue::clear();
+        }
+    }
+    provide_context(plausible.reactive());
+}
+
+/// Provides the plausible context to its children and renders the
+/// configuration in a `<meta name="plausible-config">` tag.
+///
+/// Without an explicit `config` it's read from the `<meta>` tag in the browser
+/// (so `hydrate` uses what the server rendered) or from the build time
+/// environment (see [`PlausibleConfig::from_env`]). If none is found tracking
+/// is disabled.
+#[must_use]
+#[component]
+pub fn PlausibleProvider(
+    #[prop(optional)] config: Option<PlausibleConfig>,
+    children: Children,
+) -> impl IntoView {
+    let config = config
+        .or_else(|| {
+            if is_browser() {
+                PlausibleConfig::from_meta()
+            } else {
+                None
+            }
+        })
+        .or_else(PlausibleConfig::from_env)
+        .unwrap_or_else(|| {
+            debug_warn!("No plausible config found, tracking is disabled");
+            PlausibleConfig::default().enabled(false)
+        });
+    let content = config.to_meta_content();
+    provide_plausible_context(config);
+
+    view! {
+        <meta name=META_NAME content=content/>
+        {children()}
+    }
 }
 
 /// Retrieves plausible context
@@ -25,6 +104,20 @@ pub fn expect_plausible_context() -> Plausible {
     expect_context::<Plausible>()
 }
 
+/// Whether events can be sent: tracking is enabled, the visitor didn't opt out
+/// and, if respected, there is no Do Not Track or Global Privacy Control signal.
+///
+/// Use it to avoid doing tracking work at all when nothing would be sent.
+#[must_use]
+pub fn use_tracking_allowed() -> Signal<bool> {
+    let plausible = expect_plausible_context();
+    let enabled = plausible.config().enabled;
+    let privacy_signal = plausible.privacy_signal();
+    let opted_out = plausible.opted_out();
+
+    Signal::derive(move || enabled && privacy_signal.is_none() && !opted_out.get())
+}
+
 /// Sends an event if the user focused on an item with an ID starting with `plausible-`
 ///
 /// It will send an `active_element` event with the property `data-id` with the value
@@ -35,18 +128,14 @@ pub fn track_active_elements() {
     let active_element = use_active_element();
 
     let id_with_event = Memo::new(move |_| {
-        active_element
-            .get()
-            .map(|el| {
-                el.dataset()
-                    .get("id")
-                    .filter(|id| id.starts_with("plausible-"))
-                    .map(|id| id.replace("plausible-", ""))
-            })
-            .flatten()
+        active_element.get().and_then(|el| {
+            el.get_attribute("data-id")
+                .filter(|id| id.starts_with("plausible-"))
+                .map(|id| id.replace("plausible-", ""))
+        })
     });
 
-    create_effect(move |_| {
+    Effect::new(move |_| {
         if let Some(id) = id_with_event.get() {
             expect_plausible_context()
                 .event("active_element")
@@ -55,6 +144,264 @@ pub fn track_active_elements() {
         }
     });
 }
+
+/// File extensions tracked by default, same as the official script
+pub const DEFAULT_FILE_EXTENSIONS: &[&str] = &[
+    "pdf", "xlsx", "docx", "txt", "rtf", "csv", "exe", "key", "pps", "ppt", "pptx", "7z", "pkg",
+    "rar", "gz", "zip", "avi", "mov", "mp4", "mpeg", "wmv", "midi", "mp3", "wav", "wma", "dmg",
+];
+
+/// Set on links sending their own click events, skipped by [`track_outbound_links`]
+const OUTBOUND_ATTR: &str = "data-plausible-outbound";
+/// Set on links sending their own downloads, skipped by [`track_file_downloads`]
+const DOWNLOAD_ATTR: &str = "data-plausible-download";
+
+thread_local! {
+    /// Set while [`track_outbound_links`] is listening, so
+    /// [`track_file_downloads`] leaves the links to other hosts to it
+    static TRACKING_OUTBOUND: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Link followed by a left, middle or ctrl/cmd click, unless it has the
+/// `tracked` attribute
+fn clicked_link(ev: &MouseEvent, tracked: &str) -> Option<HtmlAnchorElement> {
+    if ev.default_prevented() || ev.button() > 1 {
+        return None;
+    }
+    ev.target()
+        .and_then(|t| t.dyn_into::<Element>().ok())
+        .and_then(|el| el.closest("a[href]").ok().flatten())
+        .filter(|el| !el.has_attribute(tracked))
+        .and_then(|el| el.dyn_into::<HtmlAnchorElement>().ok())
+}
+
+/// Calls `handle` with every link clicked in the page without the `tracked`
+/// attribute
+fn on_link_click(tracked: &'static str, handle: impl Fn(&HtmlAnchorElement) + Clone + 'static) {
+    let handler = move |ev: MouseEvent| {
+        if let Some(anchor) = clicked_link(&ev, tracked) {
+            handle(&anchor);
+        }
+    };
+    let _ = use_event_listener(document(), click, handler.clone());
+    // Middle clicks don't fire `click`
+    let _ = use_event_listener(document(), Custom::<MouseEvent>::new("auxclick"), handler);
+}
+
+/// `auxclick` handler calling `handle` on middle clicks, which open the link
+/// in a new tab without firing `click`
+fn on_middle_click(handle: impl Fn(MouseEvent) + 'static) -> impl Fn(MouseEvent) + 'static {
+    move |ev: MouseEvent| {
+        if ev.button() == 1 {
+            handle(ev);
+        }
+    }
+}
+
+/// Whether the link goes to a web page in another host
+fn is_outbound(anchor: &HtmlAnchorElement) -> bool {
+    let page_host = document().location().and_then(|l| l.host().ok());
+    matches!(anchor.protocol().as_str(), "http:" | "https:")
+        && !anchor.host().is_empty()
+        && Some(anchor.host()) != page_host
+}
+
+/// Sends an `Outbound Link: Click` event when a link to another host is
+/// clicked anywhere in the page, so links don't need to be replaced with [`A`].
+///
+/// It installs a single click listener on `document`, so it also catches
+/// middle clicks and ctrl/cmd clicks that open a new tab. The event is sent
+/// with a beacon and the navigation is left untouched.
+///
+/// Like the official script, a link to a file in another host is sent as an
+/// outbound click only, also with [`track_file_downloads`].
+pub fn track_outbound_links() {
+    if !is_browser() {
+        return;
+    }
+    // The listener runs without the owner of the context
+    let plausible = expect_plausible_context();
+    TRACKING_OUTBOUND.with(|t| t.set(true));
+    on_cleanup(|| TRACKING_OUTBOUND.with(|t| t.set(false)));
+    on_link_click(OUTBOUND_ATTR, move |anchor| {
+        if is_outbound(anchor) {
+            if let Err(e) = plausible.link_click(&anchor.href()).send_beacon() {
+                debug_warn!("Plausible outbound link click not sent: {e}");
+            }
+        }
+    });
+}
+
+/// Whether `url` points to a file with one of the `extensions`
+pub fn is_file_download(url: &str, extensions: &[impl AsRef<str>]) -> bool {
+    // Only the path, `example.zip` is a domain
+    url_path(url)
+        .rsplit('/')
+        .next()
+        .and_then(|file| file.rsplit_once('.'))
+        .is_some_and(|(_, ext)| {
+            extensions
+                .iter()
+                .any(|e| e.as_ref().eq_ignore_ascii_case(ext))
+        })
+}
+
+fn send_file_download(plausible: &Plausible, url: &str) {
+    if let Err(e) = plausible.file_download(url).send_beacon() {
+        debug_warn!("Plausible file download not sent: {e}");
+    }
+}
+
+/// Sends a `File Download` event when a link to a file with one of the
+/// `extensions` is clicked anywhere in the page. See [`DEFAULT_FILE_EXTENSIONS`]
+///
+/// Like [`track_outbound_links`] it's a single `document` listener that
+/// doesn't change the navigation.
+pub fn track_file_downloads(extensions: &[&str]) {
+    if !is_browser() {
+        return;
+    }
+    let extensions: Vec<String> = extensions.iter().map(|e| (*e).to_owned()).collect();
+    // The listener runs without the owner of the context
+    let plausible = expect_plausible_context();
+    on_link_click(DOWNLOAD_ATTR, move |anchor| {
+        // One event per click
+        if TRACKING_OUTBOUND.with(Cell::get) && is_outbound(anchor) {
+            return;
+        }
+        let url = anchor.href();
+        if is_file_download(&url, &extensions) {
+            send_file_download(&plausible, &url);
+        }
+    });
+}
+
+/// `<a>` to a file that sends a `File Download` event when clicked,
+/// for pages not using [`track_file_downloads`] or files with other extensions.
+///
+/// It's a download also when the file is in another host, so
+/// [`track_outbound_links`] skips it.
+#[must_use]
+#[component]
+pub fn DownloadLink(
+    #[prop(into)] href: String,
+    /// Value of the `download` attribute, e.g. the file name
+    #[prop(optional, into)]
+    download: Option<String>,
+    #[prop(optional, into)] class: MaybeProp<String>,
+    children: Children,
+) -> impl IntoView {
+    let plausible = expect_plausible_context();
+    let handle = move |ev: MouseEvent| {
+        // The resolved url, `href` may be relative
+        if let Some(anchor) = ev
+            .current_target()
+            .and_then(|t| t.dyn_into::<HtmlAnchorElement>().ok())
+        {
+            send_file_download(&plausible, &anchor.href());
+        }
+    };
+
+    view! {
+        <a
+            href=href
+            download=download
+            class=move || class.get()
+            data-plausible-download=""
+            data-plausible-outbound=""
+            on:click=handle.clone()
+            on:auxclick=on_middle_click(handle)
+        >
+            {children()}
+        </a>
+    }
+}
+
+/// Sends a pageview every time the `leptos_router` location changes, like the
+/// official script does on `pushState`.
+///
+/// Updates that keep the same path (e.g. only the query changes) are ignored.
+/// With `include_hash` a change of the hash fragment is a new pageview too.
+///
+/// Must be used inside a `<Router/>`. Use it instead of [`PageView`], not together.
+pub fn track_router_pageviews(include_hash: bool) {
+    let location = use_location();
+
+    let page = Memo::new(move |_| {
+        if include_hash {
+            format!("{}{}", location.pathname.get(), location.hash.get())
+        } else {
+            location.pathname.get()
+        }
+    });
+
+    Effect::new(move |_| {
+        page.track();
+        let path = location.pathname.get_untracked();
+        let search = location.search.with_untracked(|s| {
+            if s.is_empty() {
+                String::new()
+            } else {
+                format!("?{s}")
+            }
+        });
+        let hash = if include_hash {
+            location.hash.get_untracked()
+        } else {
+            String::new()
+        };
+        let origin = window().location().origin().unwrap_or_default();
+        send_pageview(
+            expect_plausible_context()
+                .pageview()
+                .page_url(format!("{origin}{path}{search}{hash}")),
+        );
+    });
+}
+
+/// Sends the pageview and, if configured, starts measuring its engagement
+fn send_pageview(event: EventBuilder) {
+    let plausible = expect_plausible_context();
+    if plausible.config().track_engagement && is_browser() {
+        engagement::start(plausible, event.payload().url.clone());
+    }
+    event.send_local();
+}
+
+/// Component version of [`track_router_pageviews`].
+#[must_use]
+#[component]
+pub fn PlausibleRouterTracker(#[prop(optional)] include_hash: bool) -> impl IntoView {
+    track_router_pageviews(include_hash);
+}
+
+/// Sends a `404` event with the missing `path`, like the official script's
+/// `plausible('404')`. Use it in the `fallback` of your `<Router/>`.
+///
+/// With `set_status` (the default) the response is a `404 Not Found` during
+/// SSR with the `axum` or `actix` features, so crawlers don't index the page.
+#[must_use]
+#[component]
+pub fn NotFound(#[prop(default = true)] set_status: bool) -> impl IntoView {
+    if set_status {
+        #[cfg(feature = "axum")]
+        if let Some(res) = use_context::<leptos_axum::ResponseOptions>() {
+            res.set_status(axum::http::StatusCode::NOT_FOUND);
+        }
+        #[cfg(feature = "actix")]
+        if let Some(res) = use_context::<leptos_actix::ResponseOptions>() {
+            res.set_status(actix_web::http::StatusCode::NOT_FOUND);
+        }
+    }
+
+    // Only once, even if the experiments context changes later
+    Effect::new(move |sent: Option<()>| {
+        if sent.is_none() {
+            expect_plausible_context().not_found().send_local();
+        }
+    });
+}
+
 /// Track a standard page view event.
 #[must_use]
 #[component]
@@ -62,14 +409,22 @@ pub fn PageView() -> impl IntoView {
     let el = NodeRef::<Div>::new();
     let is_visible = use_element_visibility(el);
     let triggered_pageview = RwSignal::new(false);
+    let tracking_allowed = use_tracking_allowed();
 
-    create_effect(move |_| {
-        if is_visible.get() && !triggered_pageview.get() {
-            expect_plausible_context().pageview().send_local();
+    Effect::new(move |_| {
+        if tracking_allowed.get() && is_visible.get() && !triggered_pageview.get() {
+            send_pageview(expect_plausible_context().pageview());
             triggered_pageview.set(true);
         }
     });
 
+    // The page is left, e.g. on a route change
+    on_cleanup(|| {
+        if is_browser() {
+            engagement::stop();
+        }
+    });
+
     view! { <div node_ref=el></div> }
 }
 
@@ -84,13 +439,15 @@ pub fn TrackElement(
     #[prop(into)] name: String,
     #[prop(into, default = false)] allow_duplicates: bool,
 ) -> impl IntoView {
+    // TODO not honoured yet, the event is only sent the first time
+    let _ = allow_duplicates;
     let el = NodeRef::<Div>::new();
     let is_visible = use_element_visibility(el);
     let triggered = RwSignal::new(false);
+    let tracking_allowed = use_tracking_allowed();
 
-    let n = name.clone();
-    create_effect(move |_| {
-        if is_visible.get() && !triggered.get() {
+    Effect::new(move |_| {
+        if tracking_allowed.get() && is_visible.get() && !triggered.get() {
             let nam = name.clone();
             expect_plausible_context().event(&nam).send_local();
             triggered.set(true);
@@ -108,65 +465,229 @@ pub fn EndPage() -> impl IntoView {
     view! { <TrackElement name="endpage"/> }
 }
 
-/// Substitute for `<a>` and `<A>` that tracks the links to plausible
-// TODO implement id and attributes
+/// Scroll depths reported by default, in percent
+pub const DEFAULT_SCROLL_THRESHOLDS: &[u8] = &[25, 50, 75, 100];
+
+/// Percentage of the page that has been in the viewport
+#[must_use]
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub fn scroll_percent(scroll_top: f64, viewport_height: f64, page_height: f64) -> u8 {
+    if page_height <= 0.0 {
+        return 100;
+    }
+    ((scroll_top + viewport_height) / page_height * 100.0).clamp(0.0, 100.0) as u8
+}
+
+pub(crate) fn current_scroll_percent() -> u8 {
+    let window = window();
+    let viewport_height = window
+        .inner_height()
+        .ok()
+        .and_then(|h| h.as_f64())
+        .unwrap_or_default();
+    let page_height = document()
+        .document_element()
+        .map_or(0.0, |el| f64::from(el.scroll_height()));
+    scroll_percent(
+        window.scroll_y().unwrap_or_default(),
+        viewport_height,
+        page_height,
+    )
+}
+
+/// Sends a `Scroll Depth` event with a `depth` property the first time the
+/// visitor scrolls past each of the `thresholds` (percentages, see
+/// [`DEFAULT_SCROLL_THRESHOLDS`]).
+///
+/// Inside a `<Router/>` it starts over on every route change, so the depth is
+/// per pageview.
+pub fn track_scroll_depth(thresholds: &[u8]) {
+    if !is_browser() {
+        return;
+    }
+    let thresholds = thresholds.to_vec();
+    let max_depth = StoredValue::new(0_u8);
+    // `update` runs from the listener and animation frames, without an owner
+    let plausible = expect_plausible_context();
+
+    let update = move || {
+        let depth = current_scroll_percent();
+        let previous = max_depth.get_value();
+        if depth <= previous {
+            return;
+        }
+        max_depth.set_value(depth);
+        for threshold in thresholds.iter().filter(|t| previous < **t && **t <= depth) {
+            plausible
+                .event("Scroll Depth")
+                .prop("depth", (*threshold).into())
+                .send_local();
+        }
+    };
+
+    // Provided by `<Router/>` in the browser, `use_location` panics without it
+    if use_context::<BrowserUrl>().is_some() {
+        // Not the whole url, a query or hash change is the same page
+        let pathname = use_location().pathname;
+        let update = update.clone();
+        Effect::new(move |_| {
+            pathname.track();
+            max_depth.set_value(0);
+            // Once the new page is rendered, short pages are fully seen
+            request_animation_frame(update.clone());
+        });
+    } else {
+        request_animation_frame(update.clone());
+    }
+    let _ = use_event_listener(window(), scroll, move |_| update());
+}
+
+/// Component version of [`track_scroll_depth`], with [`DEFAULT_SCROLL_THRESHOLDS`]
+/// unless other `thresholds` are given.
+#[must_use]
+#[component]
+pub fn ScrollDepth(#[prop(optional)] thresholds: Option<Vec<u8>>) -> impl IntoView {
+    track_scroll_depth(&thresholds.unwrap_or_else(|| DEFAULT_SCROLL_THRESHOLDS.to_vec()));
+}
+
+/// Checkbox to let visitors opt out of (or back into) tracking, e.g. in
+/// a privacy settings page. See [`Plausible::opt_out`].
+///
+/// It's checked while tracking is allowed. Children are used as the label.
+#[must_use]
+#[component]
+pub fn TrackingToggle(
+    #[prop(optional, into)] class: MaybeProp<String>,
+    #[prop(optional)] children: Option<Children>,
+) -> impl IntoView {
+    let plausible = expect_plausible_context();
+    let opted_out = plausible.opted_out();
+
+    let toggle = move |ev: Event| {
+        if event_target_checked(&ev) {
+            plausible.opt_in();
+        } else {
+            plausible.opt_out();
+        }
+    };
+
+    view! {
+        <label class=move || class.get()>
+            <input type="checkbox" prop:checked=move || !opted_out.get() on:change=toggle/>
+            {children.map(|c| c())}
+        </label>
+    }
+}
+
+/// Substitute for `<a>` and `<A>` that sends an `Outbound Link: Click` event
+/// when it points to another host, or else a `File Download` one when it
+/// points to a file with one of the [`DEFAULT_FILE_EXTENSIONS`].
+///
+/// Navigation is left to the browser and `leptos_router`: internal links keep
+/// the client side routing, and modifier keys and `target` work as usual.
+/// With `track_internal` clicks on internal links are sent too, as
+/// `Internal Link: Click` events so they don't count as outbound ones.
+///
+/// `rel`, `download` and any other attribute, such as `attr:aria-label`, are
+/// passed through to the `<a>`.
 // FIXME it does not correctly find the experiment context
 #[must_use]
 #[component]
 pub fn A(
     #[prop(into)] href: String,
-    #[prop(into, default = "_self".into())] target: String,
-    #[prop(optional, into)] class: Option<AttributeValue>,
+    #[prop(optional, into)] target: Option<String>,
+    #[prop(optional, into)] class: MaybeProp<String>,
+    #[prop(optional, into)] id: Option<String>,
+    #[prop(optional, into)] rel: Option<String>,
+    #[prop(optional, into)] download: Option<String>,
+    /// Also send an `Internal Link: Click` event when the link stays within the site
+    #[prop(optional)]
+    track_internal: bool,
     /// The nodes or elements to be shown inside the link.
     children: Children,
 ) -> impl IntoView {
     // Work around to provide experiment context. Complains of using it outside
     // Suspense despite being inside one!
     let exp = use_experiment();
+    let plausible = expect_plausible_context();
 
     let handle = move |ev: MouseEvent| {
-        ev.prevent_default();
-
-        if let Some(target) = ev.target() {
-            if let Some(anchor) = target.dyn_ref::<HtmlAnchorElement>() {
-                let url = anchor.href();
-
-                expect_plausible_context()
-                    .link_click(&url)
-                    .set_experiment(exp)
-                    .send_local();
-
-                let target = anchor.target();
-
-                // Navigate to the new URL
-                if let Some(window) = window() {
-                    match target.as_str() {
-                        "_blank" => {
-                            window
-                                .open_with_url(&url)
-                                .expect("Failed to open in a new tab");
-                        }
-                        _ => {
-                            window
-                                .location()
-                                .set_href(&url)
-                                .expect("Failed to navigate");
-                        }
-                    };
-                }
-            }
+        let Some(anchor) = ev
+            .current_target()
+            .and_then(|t| t.dyn_into::<HtmlAnchorElement>().ok())
+        else {
+            return;
+        };
+        let url = anchor.href();
+        // One event per click like the official script, outbound first
+        let event = if is_outbound(&anchor) {
+            plausible.link_click(&url)
+        } else if is_file_download(&url, DEFAULT_FILE_EXTENSIONS) {
+            plausible.file_download(&url)
+        } else if track_internal {
+            plausible.internal_link_click(&url)
+        } else {
+            return;
+        };
+        // A beacon survives the navigation, a `fetch` would be cancelled
+        if let Err(e) = event.set_experiment(exp).send_beacon() {
+            debug_warn!("Plausible link click not sent: {e}");
         }
     };
 
+    // `attr:` needs literal names, `OUTBOUND_ATTR` and `DOWNLOAD_ATTR` below.
+    // Other extensions are left to `track_file_downloads`
+    let download_marker = is_file_download(&href, DEFAULT_FILE_EXTENSIONS).then_some("");
+
     view! {
         <ARouter
             href=href
-            target=target
-            class=class
-            // id=id
-            on:click=handle
+            attr:target=target
+            attr:class=move || class.get()
+            attr:id=id
+            attr:rel=rel
+            attr:download=download
+            attr:data-plausible-outbound=""
+            attr:data-plausible-download=download_marker
+            on:click=handle.clone()
+            on:auxclick=on_middle_click(handle)
         >
             {children()}
         </ARouter>
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn file_downloads() {
+        for url in [
+            "https://example.com/report.pdf",
+            "https://example.com/files/App.DMG?version=2",
+            "/static/archive.tar.gz#top",
+        ] {
+            assert!(is_file_download(url, DEFAULT_FILE_EXTENSIONS), "{url}");
+        }
+        for url in [
+            "https://example.com/",
+            "https://example.zip/",
+            "https://example.com/pdf",
+            "https://example.com/page?file=a.pdf",
+        ] {
+            assert!(!is_file_download(url, DEFAULT_FILE_EXTENSIONS), "{url}");
+        }
+        assert!(is_file_download("/data.parquet", &["parquet"]));
+    }
+
+    #[test]
+    fn scroll_percentages() {
+        assert_eq!(scroll_percent(0.0, 500.0, 2000.0), 25);
+        assert_eq!(scroll_percent(1000.0, 500.0, 2000.0), 75);
+        assert_eq!(scroll_percent(1600.0, 500.0, 2000.0), 100);
+        // Shorter than the viewport
+        assert_eq!(scroll_percent(0.0, 800.0, 400.0), 100);
+        assert_eq!(scroll_percent(0.0, 800.0, 0.0), 100);
+    }
+}
@@ -0,0 +1,280 @@
+//! Configuration of the [`Plausible`](crate::Plausible) context.
+//!
+//! It can be built in code, at build time from environment variables or at
+//! runtime from a `<meta>` tag rendered by the server so `ssr` and `hydrate`
+//! builds report to the same place.
+use leptos::prelude::document;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::dispatch::DispatchConfig;
+use crate::event::PropValue;
+use crate::is_browser;
+use crate::queue::QueueConfig;
+
+/// Name of the `<meta>` tag holding the serialized configuration.
+pub const META_NAME: &str = "plausible-config";
+
+/// Settings used by [`Plausible`](crate::Plausible) to build and send events.
+///
+/// ```
+/// # use leptos_plausible::PlausibleConfig;
+/// let config = PlausibleConfig::new("your_domain")
+///     .instance_url("https://your_plausible_instance.com")
+///     .default_prop("version", "1.2.0".into());
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+#[allow(clippy::module_name_repetitions, clippy::struct_excessive_bools)]
+pub struct PlausibleConfig {
+    /// This domain name you used when you added your site to your Plausible account
+    pub domain: String,
+    /// Plausible url for custom instances or proxies. Defaults to `https://plausible.io`
+    pub instance_url: String,
+    /// Path of the events API within the instance. Defaults to `/api/event`
+    pub api_path: String,
+    /// Same origin path where the browser sends events instead of the instance,
+    /// served by the proxy in `leptos_plausible::proxy` (`axum` and `actix` features).
+    pub proxy_path: Option<String>,
+    /// When `false` events are built but never sent
+    pub enabled: bool,
+    /// Properties added to every event. Event properties take precedence.
+    pub default_props: HashMap<String, PropValue>,
+    /// Send events from `localhost`, `127.0.0.1`, `[::1]`, `*.local` hosts and
+    /// `file://` pages. Ignored by default like the official script does.
+    pub allow_localhost: bool,
+    /// Don't send anything if the browser has Do Not Track or
+    /// Global Privacy Control enabled
+    pub respect_privacy_signals: bool,
+    /// Keep events that could not be sent in `localStorage` and retry them.
+    /// See [`crate::queue`]
+    pub queue: Option<QueueConfig>,
+    /// Coalesce and rate limit events in the browser. See [`crate::dispatch`]
+    pub dispatch: Option<DispatchConfig>,
+    /// Send `engagement` events with the time on page. See [`crate::engagement`]
+    pub track_engagement: bool,
+}
+
+impl Default for PlausibleConfig {
+    fn default() -> Self {
+        Self {
+            domain: String::new(),
+            instance_url: "https://plausible.io".into(),
+            api_path: "/api/event".into(),
+            proxy_path: None,
+            enabled: true,
+            default_props: HashMap::new(),
+            allow_localhost: false,
+            respect_privacy_signals: false,
+            queue: None,
+            dispatch: None,
+            track_engagement: false,
+        }
+    }
+}
+
+impl PlausibleConfig {
+    #[must_use]
+    pub fn new(domain: &str) -> Self {
+        Self {
+            domain: domain.into(),
+            ..Self::default()
+        }
+    }
+
+    #[must_use]
+    pub fn instance_url(mut self, instance_url: &str) -> Self {
+        self.instance_url = instance_url.into();
+        self
+    }
+
+    #[must_use]
+    pub fn api_path(mut self, api_path: &str) -> Self {
+        self.api_path = api_path.into();
+        self
+    }
+
+    /// Sends the events from the browser to `path` in your own server, which
+    /// should forward them to the instance. It helps avoiding ad blockers.
+    #[must_use]
+    pub fn proxy(mut self, path: &str) -> Self {
+        self.proxy_path = Some(path.into());
+        self
+    }
+
+    #[must_use]
+    pub const fn enabled(mut self, enabled: bool) -> Self {
+        self.enabled = enabled;
+        self
+    }
+
+    #[must_use]
+    pub const fn allow_localhost(mut self, allow_localhost: bool) -> Self {
+        self.allow_localhost = allow_localhost;
+        self
+    }
+
+    #[must_use]
+    pub const fn respect_privacy_signals(mut self, respect: bool) -> Self {
+        self.respect_privacy_signals = respect;
+        self
+    }
+
+    /// Keeps events that failed to send in `localStorage` and retries them.
+    /// See [`crate::queue`]
+    #[must_use]
+    pub const fn queue(mut self, queue: QueueConfig) -> Self {
+        self.queue = Some(queue);
+        self
+    }
+
+    /// Holds events for a short window to send duplicates once and drops the
+    /// ones over a per name rate limit. See [`crate::dispatch`]
+    #[must_use]
+    pub fn dispatch(mut self, dispatch: DispatchConfig) -> Self {
+        self.dispatch = Some(dispatch);
+        self
+    }
+
+    /// Measures the time each pageview is visible and focused and sends it as
+    /// `engagement` events. See [`crate::engagement`]
+    #[must_use]
+    pub const fn track_engagement(mut self, track_engagement: bool) -> Self {
+        self.track_engagement = track_engagement;
+        self
+    }
+
+    /// Adds new default properties overwriting if the key already exists
+    #[must_use]
+    pub fn default_props(mut self, props: HashMap<String, PropValue>) -> Self {
+        self.default_props.extend(props);
+        self
+    }
+
+    #[must_use]
+    pub fn default_prop(self, name: &str, value: PropValue) -> Self {
+        self.default_props(HashMap::from([(name.into(), value)]))
+    }
+
+    /// Url where the events are posted: the proxy path in the browser if
+    /// there is one or the instance events API.
+    #[must_use]
+    pub fn endpoint(&self) -> String {
+        match &self.proxy_path {
+            Some(path) if is_browser() => path.clone(),
+            _ => self.instance_endpoint(),
+        }
+    }
+
+    /// Full url of the events API of the instance
+    #[must_use]
+    pub fn instance_endpoint(&self) -> String {
+        format!(
+            "{}{}",
+            self.instance_url.trim_end_matches('/'),
+            self.api_path
+        )
+    }
+
+    /// Reads the configuration from environment variables at build time.
+    ///
+    /// * `PLAUSIBLE_DOMAIN` (required)
+    /// * `PLAUSIBLE_INSTANCE_URL`
+    /// * `PLAUSIBLE_API_PATH`
+    /// * `PLAUSIBLE_ENABLED` (`false` or `0` disables it)
+    ///
+    /// Returns `None` if `PLAUSIBLE_DOMAIN` was not set when compiling.
+    #[must_use]
+    pub fn from_env() -> Option<Self> {
+        Self::from_vars(
+            option_env!("PLAUSIBLE_DOMAIN"),
+            option_env!("PLAUSIBLE_INSTANCE_URL"),
+            option_env!("PLAUSIBLE_API_PATH"),
+            option_env!("PLAUSIBLE_ENABLED"),
+        )
+    }
+
+    fn from_vars(
+        domain: Option<&str>,
+        instance_url: Option<&str>,
+        api_path: Option<&str>,
+        enabled: Option<&str>,
+    ) -> Option<Self> {
+        let mut config = Self::new(domain.filter(|d| !d.is_empty())?);
+        if let Some(url) = instance_url {
+            config = config.instance_url(url);
+        }
+        if let Some(path) = api_path {
+            config = config.api_path(path);
+        }
+        if let Some(enabled) = enabled {
+            config = config.enabled(!matches!(enabled.trim(), "false" | "0"));
+        }
+        Some(config)
+    }
+
+    /// Reads the configuration from the `<meta name="plausible-config">` tag
+    /// rendered by [`PlausibleProvider`](crate::components::PlausibleProvider).
+    ///
+    /// Only works in the browser.
+    #[must_use]
+    pub fn from_meta() -> Option<Self> {
+        let content = document()
+            .query_selector(&format!("meta[name=\"{META_NAME}\"]"))
+            .ok()??
+            .get_attribute("content")?;
+        Self::from_meta_content(&content)
+    }
+
+    fn from_meta_content(content: &str) -> Option<Self> {
+        serde_json::from_str(content).ok()
+    }
+
+    /// Serialized configuration to be used as the `content` of the `<meta>` tag
+    #[must_use]
+    pub fn to_meta_content(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn endpoint_joins_url_and_path() {
+        let config = PlausibleConfig::new("example.com").instance_url("https://p.example.com/");
+        assert_eq!(config.endpoint(), "https://p.example.com/api/event");
+    }
+
+    #[test]
+    fn server_skips_proxy() {
+        let config = PlausibleConfig::new("example.com").proxy("/_p/event");
+        assert_eq!(config.endpoint(), "https://plausible.io/api/event");
+    }
+
+    #[test]
+    fn vars_require_domain() {
+        assert!(PlausibleConfig::from_vars(None, Some("https://x"), None, None).is_none());
+        assert!(PlausibleConfig::from_vars(Some(""), None, None, None).is_none());
+
+        let config =
+            PlausibleConfig::from_vars(Some("example.com"), None, Some("/p/event"), Some("0"))
+                .expect("config");
+        assert_eq!(config.domain, "example.com");
+        assert_eq!(config.api_path, "/p/event");
+        assert!(!config.enabled);
+    }
+
+    #[test]
+    fn meta_content_roundtrip() {
+        let config = PlausibleConfig::new("example.com").default_prop("version", "1".into());
+        let parsed = PlausibleConfig::from_meta_content(&config.to_meta_content());
+        assert_eq!(parsed, Some(config));
+        assert_eq!(
+            PlausibleConfig::from_meta_content(r#"{"domain":"example.com"}"#)
+                .map(|c| c.instance_url),
+            Some("https://plausible.io".into())
+        );
+    }
+}
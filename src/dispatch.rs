@@ -0,0 +1,378 @@
+//! Coalesces and rate limits the events sent from chatty pages.
+//!
+//! Enable it with [`PlausibleConfig::dispatch`](crate::PlausibleConfig::dispatch).
+//! Events are held for [`DispatchConfig::window_ms`] and identical events in
+//! that window (same name, url and props) are sent only once. Events with the
+//! same name over [`DispatchConfig::max_per_minute`] are dropped with
+//! [`IgnoreReason::RateLimited`]. Pending events are sent with a beacon as soon
+//! as the page is hidden or unloaded.
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, Weak};
+use std::time::Duration;
+
+use futures::channel::oneshot;
+use leptos::prelude::{document, set_timeout, window_event_listener_untyped};
+use leptos::task::spawn_local;
+use serde::{Deserialize, Serialize};
+use web_sys::VisibilityState;
+
+use crate::privacy::IgnoreReason;
+use crate::transport::{Transport, TransportFuture, TransportResult};
+use crate::{PlausibleError, PlausibleHeader, PlausiblePayload};
+
+const MINUTE_MS: f64 = 60_000.0;
+
+/// Settings of the [`Dispatcher`]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+#[allow(clippy::module_name_repetitions)]
+pub struct DispatchConfig {
+    /// How long events are held to coalesce them
+    pub window_ms: u64,
+    /// Events with the same name allowed per minute
+    pub max_per_minute: usize,
+    /// Overrides `max_per_minute` for some event names
+    pub limits: HashMap<String, usize>,
+}
+
+impl Default for DispatchConfig {
+    fn default() -> Self {
+        Self {
+            window_ms: 500,
+            max_per_minute: 30,
+            limits: HashMap::new(),
+        }
+    }
+}
+
+impl DispatchConfig {
+    /// Events named `name` allowed per minute
+    #[must_use]
+    pub fn limit(mut self, name: &str, max_per_minute: usize) -> Self {
+        self.limits.insert(name.into(), max_per_minute);
+        self
+    }
+
+    fn limit_for(&self, name: &str) -> usize {
+        self.limits
+            .get(name)
+            .copied()
+            .unwrap_or(self.max_per_minute)
+    }
+}
+
+/// Sliding window of the events sent in the last minute per name
+#[derive(Debug, Default)]
+struct RateLimiter(HashMap<String, VecDeque<f64>>);
+
+impl RateLimiter {
+    fn allow(&mut self, name: &str, limit: usize, now: f64) -> bool {
+        let sent = self.0.entry(name.into()).or_default();
+        while sent.front().is_some_and(|t| now - t >= MINUTE_MS) {
+            sent.pop_front();
+        }
+        if sent.len() >= limit {
+            return false;
+        }
+        sent.push_back(now);
+        true
+    }
+}
+
+#[derive(Debug)]
+struct Pending {
+    endpoint: String,
+    payload: PlausiblePayload,
+    header: PlausibleHeader,
+    /// Senders of the event and its duplicates
+    waiting: Vec<oneshot::Sender<TransportResult>>,
+}
+
+impl Pending {
+    fn resolve(self, res: &TransportResult) {
+        for tx in self.waiting {
+            let _ = tx.send(res.clone());
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    pending: Vec<Pending>,
+    limiter: RateLimiter,
+    scheduled: bool,
+}
+
+impl State {
+    /// Adds the event to the pending ones unless an identical one is already
+    /// waiting or its name is over the limit.
+    fn add(
+        &mut self,
+        endpoint: &str,
+        payload: &PlausiblePayload,
+        header: &PlausibleHeader,
+        tx: oneshot::Sender<TransportResult>,
+        now: f64,
+        config: &DispatchConfig,
+    ) -> Result<(), PlausibleError> {
+        if let Some(pending) = self
+            .pending
+            .iter_mut()
+            .find(|p| p.endpoint == endpoint && p.payload == *payload)
+        {
+            pending.waiting.push(tx);
+            return Ok(());
+        }
+        self.check(&payload.name, now, config)?;
+        self.pending.push(Pending {
+            endpoint: endpoint.into(),
+            payload: payload.clone(),
+            header: header.clone(),
+            waiting: vec![tx],
+        });
+        Ok(())
+    }
+
+    fn take(&mut self) -> Vec<Pending> {
+        self.scheduled = false;
+        std::mem::take(&mut self.pending)
+    }
+
+    fn check(
+        &mut self,
+        name: &str,
+        now: f64,
+        config: &DispatchConfig,
+    ) -> Result<(), PlausibleError> {
+        if self.limiter.allow(name, config.limit_for(name), now) {
+            Ok(())
+        } else {
+            Err(PlausibleError::Ignored(IgnoreReason::RateLimited))
+        }
+    }
+}
+
+/// State and inner transport of a [`Dispatcher`], without keeping it alive
+type WeakDispatcher = (Weak<Mutex<State>>, Weak<dyn Transport>);
+
+thread_local! {
+    static LISTENING: Cell<bool> = const { Cell::new(false) };
+    /// Dispatchers flushed when the page is hidden
+    static DISPATCHERS: RefCell<Vec<WeakDispatcher>> = const { RefCell::new(Vec::new()) };
+}
+
+fn lock(state: &Mutex<State>) -> MutexGuard<'_, State> {
+    state.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Sends the pending events with beacons, which survive the page unload
+fn flush_beacon(state: &Mutex<State>, inner: &dyn Transport) {
+    let pending = lock(state).take();
+    for p in pending {
+        let res = inner.send_beacon(&p.endpoint, &p.payload, &p.header);
+        p.resolve(&res);
+    }
+}
+
+/// Installs the page listeners once, they live as long as the page
+fn listen() {
+    if LISTENING.with(|l| l.replace(true)) {
+        return;
+    }
+    let handler = |e: web_sys::Event| {
+        if e.type_() != "pagehide" && document().visibility_state() != VisibilityState::Hidden {
+            return;
+        }
+        let alive: Vec<_> = DISPATCHERS.with(|d| {
+            d.borrow()
+                .iter()
+                .filter_map(|(state, inner)| Some((state.upgrade()?, inner.upgrade()?)))
+                .collect()
+        });
+        for (state, inner) in alive {
+            flush_beacon(&state, inner.as_ref());
+        }
+    };
+    for event in ["visibilitychange", "pagehide"] {
+        std::mem::forget(window_event_listener_untyped(event, handler));
+    }
+}
+
+/// [`Transport`] wrapping another one to coalesce and rate limit events.
+/// See the [module docs](self).
+#[derive(Clone, Debug)]
+pub struct Dispatcher {
+    inner: Arc<dyn Transport>,
+    config: Arc<DispatchConfig>,
+    state: Arc<Mutex<State>>,
+}
+
+impl Dispatcher {
+    /// Wraps `inner` and flushes the pending events when the page is hidden
+    /// or unloaded. Only works in the browser.
+    #[must_use]
+    pub fn new(inner: Arc<dyn Transport>, config: DispatchConfig) -> Self {
+        let dispatcher = Self {
+            inner,
+            config: Arc::new(config),
+            state: Arc::default(),
+        };
+        listen();
+        // Weak so the dispatcher is dropped with its context
+        DISPATCHERS.with(|d| {
+            let mut dispatchers = d.borrow_mut();
+            dispatchers.retain(|(state, _)| state.strong_count() > 0);
+            dispatchers.push((
+                Arc::downgrade(&dispatcher.state),
+                Arc::downgrade(&dispatcher.inner),
+            ));
+        });
+        dispatcher
+    }
+
+    /// Sends the pending events now
+    pub fn flush(&self) {
+        let pending = self.take();
+        if pending.is_empty() {
+            return;
+        }
+        let inner = Arc::clone(&self.inner);
+        spawn_local(async move {
+            for p in pending {
+                let res = inner.send(&p.endpoint, &p.payload, &p.header).await;
+                p.resolve(&res);
+            }
+        });
+    }
+
+    /// Sends the pending events with beacons, which survive the page unload
+    pub fn flush_beacon(&self) {
+        flush_beacon(&self.state, self.inner.as_ref());
+    }
+
+    fn take(&self) -> Vec<Pending> {
+        lock(&self.state).take()
+    }
+}
+
+impl Transport for Dispatcher {
+    fn send<'a>(
+        &'a self,
+        endpoint: &'a str,
+        payload: &'a PlausiblePayload,
+        header: &'a PlausibleHeader,
+    ) -> TransportFuture<'a> {
+        let (tx, rx) = oneshot::channel();
+        let mut state = lock(&self.state);
+        let added = state.add(
+            endpoint,
+            payload,
+            header,
+            tx,
+            js_sys::Date::now(),
+            &self.config,
+        );
+        let schedule = added.is_ok() && !state.scheduled;
+        state.scheduled |= schedule;
+        drop(state);
+        if schedule {
+            let dispatcher = self.clone();
+            set_timeout(
+                move || dispatcher.flush(),
+                Duration::from_millis(self.config.window_ms),
+            );
+        }
+        Box::pin(async move {
+            added?;
+            rx.await
+                .unwrap_or_else(|_| Err(PlausibleError::Network("event dropped".into())))
+        })
+    }
+
+    /// Sent right away after the pending events, since the page is probably
+    /// going away
+    fn send_beacon(
+        &self,
+        endpoint: &str,
+        payload: &PlausiblePayload,
+        header: &PlausibleHeader,
+    ) -> TransportResult {
+        lock(&self.state).check(&payload.name, js_sys::Date::now(), &self.config)?;
+        self.flush_beacon();
+        self.inner.send_beacon(endpoint, payload, header)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rate_limit_per_name() {
+        let config = DispatchConfig {
+            max_per_minute: 2,
+            ..DispatchConfig::default()
+        }
+        .limit("pageview", 1);
+        let mut limiter = RateLimiter::default();
+        let mut allow = |name, now| limiter.allow(name, config.limit_for(name), now);
+
+        assert!(allow("click", 0.0));
+        assert!(allow("click", 1_000.0));
+        assert!(!allow("click", 2_000.0));
+        assert!(allow("pageview", 2_000.0));
+        assert!(!allow("pageview", 3_000.0));
+        // The first click is out of the window
+        assert!(allow("click", 60_000.0));
+        assert!(!allow("click", 60_500.0));
+    }
+
+    #[test]
+    fn coalesces_identical_events() {
+        let config = DispatchConfig::default();
+        let header = PlausibleHeader::default();
+        let mut state = State::default();
+        let mut add = |p: &PlausiblePayload| {
+            let (tx, _rx) = oneshot::channel();
+            state.add("/api/event", p, &header, tx, 0.0, &config)
+        };
+
+        assert!(add(&PlausiblePayload::test("click", "https://example.com/")).is_ok());
+        assert!(add(&PlausiblePayload::test("click", "https://example.com/")).is_ok());
+        assert!(add(&PlausiblePayload::test(
+            "click",
+            "https://example.com/other"
+        ))
+        .is_ok());
+
+        assert_eq!(state.pending.len(), 2);
+        assert_eq!(state.pending[0].waiting.len(), 2);
+    }
+
+    #[test]
+    fn duplicates_share_the_result() {
+        let config = DispatchConfig::default();
+        let header = PlausibleHeader::default();
+        let mut state = State::default();
+        let event = PlausiblePayload::test("click", "https://example.com/");
+        let (tx1, rx1) = oneshot::channel();
+        let (tx2, rx2) = oneshot::channel();
+        state
+            .add("/api/event", &event, &header, tx1, 0.0, &config)
+            .expect("added");
+        state
+            .add("/api/event", &event, &header, tx2, 0.0, &config)
+            .expect("coalesced");
+
+        let pending = state.pending.pop().expect("pending event");
+        pending.resolve(&Ok(crate::SendOutcome::Accepted));
+
+        for rx in [rx1, rx2] {
+            assert_eq!(
+                futures::executor::block_on(rx).expect("result"),
+                Ok(crate::SendOutcome::Accepted)
+            );
+        }
+    }
+}
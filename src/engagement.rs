@@ -0,0 +1,184 @@
+//! Time on page, sent as `engagement` events paired with the pageviews.
+//!
+//! Enable it with [`PlausibleConfig::track_engagement`](crate::PlausibleConfig::track_engagement).
+//! Every pageview sent by [`PageView`](crate::components::PageView) or
+//! [`track_router_pageviews`](crate::components::track_router_pageviews)
+//! starts measuring the time the page is visible and focused. It's paused
+//! while the tab is hidden or the window loses the focus, and sent when the
+//! page is hidden, on the next pageview (a route change) and on `pagehide`.
+use std::cell::{Cell, RefCell};
+
+use leptos::logging::debug_warn;
+use leptos::prelude::{document, window_event_listener_untyped};
+use web_sys::VisibilityState;
+
+use crate::components::current_scroll_percent;
+use crate::Plausible;
+
+/// Time a page has been engaged with
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Timer {
+    total_ms: f64,
+    /// Start of the current engagement, in milliseconds
+    since: Option<f64>,
+}
+
+impl Timer {
+    #[must_use]
+    pub const fn running(now: f64) -> Self {
+        Self {
+            total_ms: 0.0,
+            since: Some(now),
+        }
+    }
+
+    pub fn resume(&mut self, now: f64) {
+        self.since.get_or_insert(now);
+    }
+
+    pub fn pause(&mut self, now: f64) {
+        if let Some(since) = self.since.take() {
+            self.total_ms += (now - since).max(0.0);
+        }
+    }
+
+    /// Engaged milliseconds until `now`, restarting the count
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn take(&mut self, now: f64) -> u64 {
+        let running = self.since.is_some();
+        self.pause(now);
+        let total = self.total_ms.round() as u64;
+        self.total_ms = 0.0;
+        if running {
+            self.since = Some(now);
+        }
+        total
+    }
+}
+
+#[derive(Debug)]
+struct Session {
+    plausible: Plausible,
+    url: String,
+    timer: Timer,
+    scroll_depth: u8,
+}
+
+impl Session {
+    fn send(&mut self, beacon: bool) {
+        let engagement_time = self.timer.take(js_sys::Date::now());
+        if engagement_time == 0 {
+            return;
+        }
+        let event = self
+            .plausible
+            .engagement(&self.url, engagement_time, self.scroll_depth);
+        if beacon {
+            if let Err(e) = event.send_beacon() {
+                debug_warn!("Plausible engagement not sent: {e}");
+            }
+        } else {
+            event.send_local();
+        }
+    }
+}
+
+thread_local! {
+    static SESSION: RefCell<Option<Session>> = const { RefCell::new(None) };
+    static LISTENING: Cell<bool> = const { Cell::new(false) };
+}
+
+fn is_engaged() -> bool {
+    document().visibility_state() == VisibilityState::Visible
+        && document().has_focus().unwrap_or(true)
+}
+
+/// Sends the engagement of the previous pageview, if any, and starts
+/// measuring the one of `url`.
+pub fn start(plausible: Plausible, url: String) {
+    listen();
+    let now = js_sys::Date::now();
+    let mut timer = Timer::running(now);
+    if !is_engaged() {
+        timer.pause(now);
+    }
+    let previous = SESSION.with(|s| {
+        s.borrow_mut().replace(Session {
+            plausible,
+            url,
+            timer,
+            scroll_depth: current_scroll_percent(),
+        })
+    });
+    if let Some(mut previous) = previous {
+        previous.send(false);
+    }
+}
+
+/// Sends the engagement of the current pageview and stops measuring it.
+pub fn stop() {
+    if let Some(mut session) = SESSION.with(|s| s.borrow_mut().take()) {
+        session.send(false);
+    }
+}
+
+fn with_session(f: impl FnOnce(&mut Session)) {
+    SESSION.with(|s| {
+        if let Some(session) = s.borrow_mut().as_mut() {
+            f(session);
+        }
+    });
+}
+
+/// Installs the page listeners once, they live as long as the page
+fn listen() {
+    if LISTENING.with(|l| l.replace(true)) {
+        return;
+    }
+    let on_change = |_| {
+        let now = js_sys::Date::now();
+        let hidden = document().visibility_state() == VisibilityState::Hidden;
+        with_session(|session| {
+            if is_engaged() {
+                session.timer.resume(now);
+            } else {
+                session.timer.pause(now);
+            }
+            // The page may never be shown again, e.g. closed on mobile
+            if hidden {
+                session.send(true);
+            }
+        });
+    };
+    for event in ["visibilitychange", "focus", "blur"] {
+        std::mem::forget(window_event_listener_untyped(event, on_change));
+    }
+    std::mem::forget(window_event_listener_untyped("pagehide", |_| {
+        if let Some(mut session) = SESSION.with(|s| s.borrow_mut().take()) {
+            session.send(true);
+        }
+    }));
+    std::mem::forget(window_event_listener_untyped("scroll", |_| {
+        let depth = current_scroll_percent();
+        with_session(|session| session.scroll_depth = session.scroll_depth.max(depth));
+    }));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn counts_only_engaged_time() {
+        let mut timer = Timer::running(0.0);
+        timer.pause(1_000.0);
+        // Hidden for a while
+        timer.resume(5_000.0);
+        timer.resume(6_000.0);
+        assert_eq!(timer.take(7_500.0), 3_500);
+        // Starts over after being sent
+        assert_eq!(timer.take(8_000.0), 500);
+        timer.pause(8_000.0);
+        assert_eq!(timer.take(9_000.0), 0);
+    }
+}
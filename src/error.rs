@@ -0,0 +1,59 @@
+//! Results of sending events
+use std::fmt;
+
+use crate::privacy::IgnoreReason;
+
+/// Why an event could not be sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[allow(clippy::module_name_repetitions)]
+pub enum PlausibleError {
+    /// The event could not be serialized to JSON
+    Serialization(String),
+    /// The HTTP request could not be built
+    Request(String),
+    /// The request failed before getting a response
+    Network(String),
+    /// The instance answered with something other than `202 Accepted`
+    Status(u16),
+    /// Tracking is disabled in the [`PlausibleConfig`](crate::PlausibleConfig)
+    Disabled,
+    /// The event was dropped on purpose, e.g. on `localhost`
+    Ignored(IgnoreReason),
+}
+
+impl fmt::Display for PlausibleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Serialization(e) => write!(f, "could not serialize the event: {e}"),
+            Self::Request(e) => write!(f, "could not build the request: {e}"),
+            Self::Network(e) => write!(f, "network error: {e}"),
+            Self::Status(status) => write!(f, "unexpected response status {status}"),
+            Self::Disabled => write!(f, "tracking is disabled"),
+            Self::Ignored(reason) => write!(f, "ignoring event: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for PlausibleError {}
+
+impl PlausibleError {
+    /// Whether sending the same event later may work: network errors,
+    /// rate limits and server errors.
+    #[must_use]
+    pub const fn is_retryable(&self) -> bool {
+        match self {
+            Self::Network(_) => true,
+            Self::Status(status) => *status == 429 || *status >= 500,
+            _ => false,
+        }
+    }
+}
+
+/// How an event left the application.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendOutcome {
+    /// The instance answered `202 Accepted`
+    Accepted,
+    /// The request was sent but its response can't be read (e.g. beacons)
+    Opaque,
+}
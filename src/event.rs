@@ -1,33 +1,41 @@
 //! Track pageviews and send custom events to plausible
 //!
 //!
-use gloo_net::http::Request;
-use leptos::{self, document, logging::debug_warn, spawn_local, window};
+use leptos::logging::debug_warn;
+use leptos::prelude::{document, window, RwSignal, Set, Signal};
+use leptos::task::spawn_local;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use wasm_bindgen::JsValue;
+use std::fmt;
+use std::rc::Rc;
+use std::sync::Arc;
+use web_sys::VisibilityState;
 
 use super::experiments::{use_experiment_props, ExperimentCtx};
+use crate::dispatch::Dispatcher;
+use crate::privacy::{local_location, privacy_signal, store_opt_out, stored_opt_out, IgnoreReason};
+use crate::queue;
+use crate::transport::{default_transport, Transport};
+use crate::{is_browser, PlausibleConfig, PlausibleError, SendOutcome};
 
 /// Main intro class handling Plausible events API.
 ///
 /// ```
-/// # use leptos_plausible::Plausible;
+/// # use leptos_plausible::{Plausible, PlausibleError};
 /// # use std::collections::HashMap;
-/// # async fn no_run() {
+/// # async fn no_run() -> Result<(), PlausibleError> {
 /// let p = Plausible::new("your_domain");
 ///
-/// p.pageview().send().await;
-///
-/// let mut event = p.event("MyCustomEvent");
+/// p.pageview().send().await?;
 ///
-/// event.props(HashMap::from([
+/// let event = p.event("MyCustomEvent").props(HashMap::from([
 ///         ("experiment".into(), "experiment_name".into()),
 ///         ("variant".into(), "A".into()),
 ///     ]));
-///     
-/// event.send().await;
 ///
+/// event.send().await?;
+///
+/// # Ok(())
 /// # }
 /// ```
 ///
@@ -40,12 +48,20 @@ use super::experiments::{use_experiment_props, ExperimentCtx};
 /// # }
 /// ```
 ///
+/// For anything else use a [`PlausibleConfig`]:
+///
+/// ```
+/// # use leptos_plausible::{Plausible, PlausibleConfig};
+/// let p = Plausible::from(PlausibleConfig::new("your_domain").api_path("/_p/event"));
+/// ```
+///
 #[derive(Clone, Debug)]
 pub struct Plausible {
-    /// This domain name you used when you added your site to your Plausible account
-    domain: String,
-    /// Plausibe url for custom instances. By `new()` constructor sets it as `https://plausible.io`
-    plausible_url: String,
+    config: Arc<PlausibleConfig>,
+    transport: Arc<dyn Transport>,
+    /// Only created by [`provide_plausible_context`](crate::components::provide_plausible_context)
+    /// since there may be no reactive runtime yet
+    opted_out: Option<RwSignal<bool>>,
 }
 
 impl Plausible {
@@ -57,6 +73,39 @@ impl Plausible {
             .props(HashMap::from([(String::from("url"), outbound_url.into())]))
     }
 
+    /// `Internal Link: Click` event with the `url` of a link within the site
+    /// as property, kept apart from the outbound link clicks
+    #[must_use]
+    pub fn internal_link_click(&self, url: &str) -> EventBuilder {
+        self.build_event(EventName::InternalLinkClick)
+            .props(HashMap::from([(String::from("url"), url.into())]))
+    }
+
+    /// `File Download` event with the file `url` as property
+    #[must_use]
+    pub fn file_download(&self, url: &str) -> EventBuilder {
+        self.build_event(EventName::FileDownload)
+            .props(HashMap::from([(String::from("url"), url.into())]))
+    }
+
+    /// `404` event with the `path` of the current page as property
+    #[must_use]
+    pub fn not_found(&self) -> EventBuilder {
+        let event = self.build_event(EventName::NotFound);
+        let path = url_path(&event.body.url).to_owned();
+        event.prop("path", path.into())
+    }
+
+    /// `engagement` event about the pageview of `url`, with the time it was
+    /// visible and focused since the last one. See [`crate::engagement`]
+    #[must_use]
+    pub fn engagement(&self, url: &str, engagement_time: u64, scroll_depth: u8) -> EventBuilder {
+        let mut event = self.build_event(EventName::Engagement).page_url(url.into());
+        event.body.engagement_time = Some(engagement_time);
+        event.body.scroll_depth = Some(scroll_depth);
+        event
+    }
+
     #[must_use]
     pub fn pageview(&self) -> EventBuilder {
         self.build_event(EventName::Pageview)
@@ -66,79 +115,252 @@ impl Plausible {
     pub fn event(&self, name: &str) -> EventBuilder {
         self.build_event(EventName::Custom(name.into()))
     }
+
+    /// Event about an explicit page and visitor instead of the current one.
+    ///
+    /// It doesn't read the browser globals nor the request being served, so
+    /// it can be sent from native code, e.g. on webhooks, signups or API calls.
+    ///
+    /// ```
+    /// # use leptos_plausible::{PageContext, Plausible, PlausibleError};
+    /// # async fn no_run() -> Result<(), PlausibleError> {
+    /// let context = PageContext::new("https://example.com/signup")
+    ///     .user_agent("Mozilla/5.0 ...")
+    ///     .ip("203.0.113.7");
+    /// Plausible::new("example.com")
+    ///     .event_with_context("Signup", context)
+    ///     .send()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[must_use]
+    pub fn event_with_context(&self, name: &str, context: PageContext) -> EventBuilder {
+        self.build_event_with(EventName::Custom(name.into()), context)
+    }
 }
 
 impl Plausible {
     #[must_use]
     pub fn new(domain: &str) -> Self {
-        Self {
-            domain: domain.into(),
-            plausible_url: "https://plausible.io".into(),
-        }
+        Self::from(PlausibleConfig::new(domain))
     }
 
     #[must_use]
     pub fn new_private(domain: &str, instance_url: &str) -> Self {
-        Self {
-            domain: domain.into(),
-            plausible_url: instance_url.into(),
+        Self::from(PlausibleConfig::new(domain).instance_url(instance_url))
+    }
+
+    #[must_use]
+    pub fn config(&self) -> &PlausibleConfig {
+        &self.config
+    }
+
+    /// Stops sending events from this browser. Same as setting
+    /// `localStorage.plausible_ignore = "true"` for the official script.
+    /// Events waiting in the [queue](crate::queue) are dropped too.
+    pub fn opt_out(&self) {
+        store_opt_out(true);
+        queue::clear();
+        if let Some(opted_out) = self.opted_out {
+            opted_out.set(true);
+        }
+    }
+
+    /// Reverts [`opt_out`](Self::opt_out)
+    pub fn opt_in(&self) {
+        store_opt_out(false);
+        if let Some(opted_out) = self.opted_out {
+            opted_out.set(false);
+        }
+    }
+
+    /// Reads the opt out from `localStorage`
+    #[must_use]
+    pub fn is_opted_out(&self) -> bool {
+        stored_opt_out()
+    }
+
+    /// Reactive version of [`is_opted_out`](Self::is_opted_out) updated by
+    /// [`opt_out`](Self::opt_out) and [`opt_in`](Self::opt_in) of the context.
+    #[must_use]
+    pub fn opted_out(&self) -> Signal<bool> {
+        self.opted_out
+            .map_or_else(|| Signal::derive(stored_opt_out), Signal::from)
+    }
+
+    /// Do Not Track or Global Privacy Control signal preventing events from
+    /// being sent, if [`PlausibleConfig::respect_privacy_signals`] is set.
+    #[must_use]
+    pub fn privacy_signal(&self) -> Option<IgnoreReason> {
+        if self.config.respect_privacy_signals {
+            privacy_signal()
+        } else {
+            None
+        }
+    }
+
+    /// Creates the reactive state and, if configured, the [`Dispatcher`].
+    /// Must be called within a reactive owner.
+    pub(crate) fn reactive(mut self) -> Self {
+        self.opted_out = Some(RwSignal::new(stored_opt_out()));
+        if let Some(dispatch) = self.config.dispatch.clone().filter(|_| is_browser()) {
+            self.transport = Arc::new(Dispatcher::new(Arc::clone(&self.transport), dispatch));
         }
+        self
+    }
+
+    pub(crate) fn transport(&self) -> Arc<dyn Transport> {
+        Arc::clone(&self.transport)
     }
 
-    fn default_url() -> String {
-        window()
-            .location()
-            .href()
-            .expect("ERROR with plausible event: url")
+    /// Replaces how events are delivered. See [`crate::transport`]
+    #[must_use]
+    pub fn with_transport(mut self, transport: impl Transport + 'static) -> Self {
+        self.transport = Arc::new(transport);
+        self
     }
 
     fn build_event(&self, name: EventName) -> EventBuilder {
-        let header = PlausibleHeader {
-            user_agent: window()
-                .navigator()
-                .user_agent()
-                .unwrap_or_else(|_| "ERROR".into()),
-            // FIXME this is wrong
-            x_forwarded_for: window()
-                .location()
-                .href()
-                .expect("Error with plausible event"),
-        };
-        let referrer = document().referrer();
+        self.build_event_with(name, PageContext::current())
+            .experiments()
+    }
+
+    fn build_event_with(&self, name: EventName, context: PageContext) -> EventBuilder {
+        let PageContext {
+            url,
+            referrer,
+            header,
+        } = context;
         let body = PlausiblePayload {
             name: name.into(),
-            url: Self::default_url(),
-            domain: self.domain.clone(),
-            referrer: if referrer.is_empty() {
-                None
-            } else {
-                Some(referrer)
-            },
+            url,
+            domain: self.config.domain.clone(),
+            referrer,
             props: None,
             revenue: None,
+            scroll_depth: None,
+            engagement_time: None,
             screen_width: None,
         };
 
         EventBuilder {
             header,
             body,
-            plausible_url: self.plausible_url.clone(),
+            config: Arc::clone(&self.config),
+            transport: Arc::clone(&self.transport),
+            on_error: None,
+            privacy_signal: self.privacy_signal(),
+        }
+        .props(self.config.default_props.clone())
+    }
+}
+
+impl From<PlausibleConfig> for Plausible {
+    fn from(config: PlausibleConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+            transport: default_transport(),
+            opted_out: None,
         }
-        .experiments()
     }
 }
 
+/// Path of `url` without the query and the fragment
+pub fn url_path(url: &str) -> &str {
+    let path = url
+        .split_once("://")
+        .map_or(url, |(_, rest)| rest.find('/').map_or("/", |i| &rest[i..]));
+    path.split(['?', '#']).next().unwrap_or(path)
+}
+
+/// Page and visitor an event is about
+#[derive(Debug, Clone, Default)]
+pub struct PageContext {
+    /// See [`PlausiblePayload::url`]
+    pub url: String,
+    pub referrer: Option<String>,
+    pub header: PlausibleHeader,
+}
+
+impl PageContext {
+    /// Context of the current page: from the browser globals or, during SSR,
+    /// from the request being served (`ssr` feature).
+    fn current() -> Self {
+        if is_browser() {
+            Self::from_browser()
+        } else {
+            Self::from_request().unwrap_or_default()
+        }
+    }
+
+    fn from_browser() -> Self {
+        let referrer = document().referrer();
+        Self {
+            url: window().location().href().unwrap_or_default(),
+            referrer: if referrer.is_empty() {
+                None
+            } else {
+                Some(referrer)
+            },
+            header: PlausibleHeader {
+                user_agent: window().navigator().user_agent().unwrap_or_default(),
+                // The instance sees the IP of the browser itself
+                x_forwarded_for: None,
+            },
+        }
+    }
+
+    #[cfg(not(feature = "ssr"))]
+    #[allow(clippy::missing_const_for_fn)]
+    fn from_request() -> Option<Self> {
+        None
+    }
+
+    /// Context of an event not coming from a page being rendered.
+    /// See [`Plausible::event_with_context`]
+    #[must_use]
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.into(),
+            ..Self::default()
+        }
+    }
+
+    #[must_use]
+    pub fn referrer(mut self, referrer: &str) -> Self {
+        self.referrer = Some(referrer.into());
+        self
+    }
+
+    #[must_use]
+    pub fn user_agent(mut self, user_agent: &str) -> Self {
+        self.header.user_agent = user_agent.into();
+        self
+    }
+
+    /// IP of the visitor, sent as `X-Forwarded-For`
+    #[must_use]
+    pub fn ip(mut self, ip: &str) -> Self {
+        self.header.x_forwarded_for = Some(ip.into());
+        self
+    }
+}
+
+/// Headers identifying the visitor. Only needed when sending from the server,
+/// the browser already sends them.
 // From https://github.com/goddtriffin/plausible-rs/ under MIT license
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 #[allow(clippy::module_name_repetitions)]
-struct PlausibleHeader {
+pub struct PlausibleHeader {
     pub user_agent: String,
-    pub x_forwarded_for: String,
+    /// IP of the visitor
+    pub x_forwarded_for: Option<String>,
 }
 
 impl PlausibleHeader {
     #[must_use]
-    pub const fn new(user_agent: String, x_forwarded_for: String) -> Self {
+    pub const fn new(user_agent: String, x_forwarded_for: Option<String>) -> Self {
         Self {
             user_agent,
             x_forwarded_for,
@@ -153,13 +375,14 @@ impl PlausibleHeader {
 /// the API returns HTTP 202, but revenue data associated with it is discarded.
 ///
 /// Not available to the community edition
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct RevenueValue {
     currency: String,
     amount: String,
 }
 
 impl RevenueValue {
+    #[must_use]
     pub fn new(currency: &str, amount: &str) -> Self {
         Self {
             currency: currency.into(),
@@ -169,8 +392,9 @@ impl RevenueValue {
 }
 
 // From https://github.com/goddtriffin/plausible-rs/ under MIT license
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct PlausiblePayload {
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[allow(clippy::module_name_repetitions)]
+pub struct PlausiblePayload {
     /// Name of the event
     pub name: String,
     /// Domain name of the site in plausible
@@ -188,6 +412,30 @@ struct PlausiblePayload {
     pub props: Option<HashMap<String, PropValue>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub revenue: Option<RevenueValue>,
+    /// Maximum scroll percentage of an `engagement` event
+    #[serde(rename = "sd", skip_serializing_if = "Option::is_none")]
+    pub scroll_depth: Option<u8>,
+    /// Milliseconds the page was visible and focused, for `engagement` events
+    #[serde(rename = "e", skip_serializing_if = "Option::is_none")]
+    pub engagement_time: Option<u64>,
+}
+
+#[cfg(test)]
+impl PlausiblePayload {
+    /// Event for `example.com` with only a name and an url
+    pub(crate) fn test(name: &str, url: &str) -> Self {
+        Self {
+            name: name.into(),
+            domain: "example.com".into(),
+            url: url.into(),
+            referrer: None,
+            screen_width: None,
+            props: None,
+            revenue: None,
+            scroll_depth: None,
+            engagement_time: None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -195,9 +443,11 @@ struct PlausiblePayload {
 pub enum EventName {
     Pageview,
     OutboundLinkClick,
+    InternalLinkClick,
     FileDownload,
     Purchase,
     NotFound,
+    Engagement,
     Custom(String),
 }
 
@@ -207,52 +457,75 @@ impl Into<String> for EventName {
         match self {
             Self::Pageview => "pageview".to_string(),
             Self::OutboundLinkClick => "Outbound Link: Click".to_string(),
+            Self::InternalLinkClick => "Internal Link: Click".to_string(),
             Self::FileDownload => "File Download".to_string(),
             Self::Purchase => "Purchase".to_string(),
             Self::NotFound => "404".to_string(),
+            Self::Engagement => "engagement".to_string(),
             Self::Custom(s) => s,
         }
     }
 }
 
+/// Error callback of an [`EventBuilder`].
+///
+/// Not a leptos `Callback`: those live in the reactive arena and panic once
+/// the component that built the event is unmounted, e.g. on navigation.
+#[derive(Clone)]
+struct OnError(Rc<dyn Fn(PlausibleError)>);
+
+impl fmt::Debug for OnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("OnError")
+    }
+}
+
 #[derive(Debug, Clone)]
 #[allow(clippy::module_name_repetitions)]
 pub struct EventBuilder {
-    plausible_url: String,
+    config: Arc<PlausibleConfig>,
+    transport: Arc<dyn Transport>,
     header: PlausibleHeader,
     body: PlausiblePayload,
+    on_error: Option<OnError>,
+    privacy_signal: Option<IgnoreReason>,
 }
 
 impl EventBuilder {
     /// Adds new properties overwriting if the key already exists
+    #[must_use]
     pub fn props(mut self, props: HashMap<String, PropValue>) -> Self {
         match &mut self.body.props {
             None => self.body.props = Some(props),
             Some(existing) => {
-                for (k, v) in props.clone().drain() {
+                for (k, v) in props {
                     existing.insert(k, v);
                 }
             }
-        };
+        }
         self
     }
 
+    #[must_use]
     pub fn prop(self, name: &str, value: PropValue) -> Self {
         self.props(HashMap::from([(name.into(), value)]))
     }
 
     /// Adds revenue information. As of now it does not work
     /// on plausible community
+    #[must_use]
     pub fn revenue(mut self, revenue: RevenueValue) -> Self {
         self.body.revenue = Some(revenue);
         self
     }
 
+    #[must_use]
     pub fn referrer(mut self, referrer: String) -> Self {
         self.body.referrer = Some(referrer);
         self
     }
 
+    #[must_use]
     pub const fn screen_width(mut self, screen_width: usize) -> Self {
         self.body.screen_width = Some(screen_width);
         self
@@ -262,12 +535,14 @@ impl EventBuilder {
     ///
     /// WARNING: Does not run well inside `spawn_local` or `on:` functions
     // FIXME
+    #[must_use]
     pub fn experiments(self) -> Self {
         match use_experiment_props() {
             Some(props) => self.props(props),
             None => self,
         }
     }
+    #[must_use]
     pub fn set_experiment(self, experiment: Option<ExperimentCtx>) -> Self {
         match experiment {
             Some(e) => self.props(e.to_plausible()),
@@ -275,46 +550,174 @@ impl EventBuilder {
         }
     }
 
+    /// Replaces the full URL of the page where the event was triggered.
+    ///
+    /// Unlike [`url`](Self::url) it's used as is.
+    #[must_use]
+    pub fn page_url(mut self, url: String) -> Self {
+        self.body.url = url;
+        self
+    }
+
+    /// Replaces the page and visitor of the event, like
+    /// [`Plausible::event_with_context`] does for custom events, so any event
+    /// can be sent from native code:
+    ///
+    /// ```
+    /// # use leptos_plausible::{PageContext, Plausible};
+    /// let context = PageContext::new("https://example.com/old-page").user_agent("Mozilla/5.0 ...");
+    /// let event = Plausible::new("example.com").not_found().context(context);
+    /// ```
+    #[must_use]
+    pub fn context(mut self, context: PageContext) -> Self {
+        let PageContext {
+            url,
+            referrer,
+            header,
+        } = context;
+        let not_found: String = EventName::NotFound.into();
+        if self.body.name == not_found {
+            // The path of `not_found` is the one of the page
+            self = self.prop("path", url_path(&url).to_owned().into());
+        }
+        self.body.url = url;
+        self.body.referrer = referrer;
+        self.header = header;
+        self
+    }
+
     /// Use it to specify custom locations for your page URL.
     ///
     /// For example if they include identifiers lile PII and UUID and you don't want to send those.
     /// You can send just `/user` to avoid sending sensitive data and improve
     /// Top Pages statistics.
     pub fn url(&mut self, url: &str) -> &mut Self {
-        let url_with_params = format!(
-            "{url}{}",
-            window().location().search().expect("ERR with plausible")
-        );
-        self.body.url = url_with_params;
+        let search = if is_browser() {
+            window().location().search().unwrap_or_default()
+        } else {
+            String::new()
+        };
+        self.body.url = format!("{url}{search}");
         self
     }
 
-    pub async fn send(self) {
-        // TODO disable sending event if localhost like done in the official script
-        // TODO don't send pageview event if already visited before (window.history)
-        // FIXME this from_serde should work but returns JSValue(Object(...)) which fails
-        //let body = JsValue::from_serde(&self.body).expect("ERR serializing");
-        let body = JsValue::from_str(&serde_json::to_string(&self.body).expect("ERR"));
+    /// What will be sent
+    #[must_use]
+    pub const fn payload(&self) -> &PlausiblePayload {
+        &self.body
+    }
 
-        let resp = Request::post(&format!("{}/api/event", self.plausible_url))
-            .referrer_policy(web_sys::ReferrerPolicy::StrictOriginWhenCrossOrigin)
-            .mode(web_sys::RequestMode::NoCors)
-            .header("Cache-Control", "no-cache")
-            .header("Content-Type", "application/json")
-            .body(body);
+    /// Called by [`send_local`](Self::send_local) if the event could not be sent.
+    ///
+    /// It may run after the component sending the event is gone.
+    #[must_use]
+    pub fn on_error(mut self, on_error: impl Fn(PlausibleError) + 'static) -> Self {
+        self.on_error = Some(OnError(Rc::new(on_error)));
+        self
+    }
 
-        let resp = resp.expect("Error building the body").send().await;
+    /// Sends the event to the configured plausible instance using
+    /// the [`Transport`] of the context.
+    ///
+    /// # Errors
+    ///
+    /// See [`PlausibleError`]
+    #[allow(clippy::future_not_send)]
+    pub async fn send(self) -> Result<SendOutcome, PlausibleError> {
+        self.check()?;
+        self.transport
+            .send(&self.config.endpoint(), &self.body, &self.header)
+            .await
+    }
+
+    /// Sends the event with the browser's `navigator.sendBeacon` (or a
+    /// `keepalive` fetch) so it's delivered even if the page navigates away
+    /// right after, e.g. when following a link.
+    ///
+    /// # Errors
+    ///
+    /// See [`PlausibleError`]
+    pub fn send_beacon(self) -> Result<SendOutcome, PlausibleError> {
+        self.check()?;
+        self.transport
+            .send_beacon(&self.config.endpoint(), &self.body, &self.header)
+    }
+
+    /// Whether the event can be sent at all
+    fn check(&self) -> Result<(), PlausibleError> {
+        // TODO don't send pageview event if already visited before (window.history)
+        if !self.config.enabled {
+            return Err(PlausibleError::Disabled);
+        }
+        // Outside of a request there is no page to take it from
+        if self.body.url.is_empty() {
+            return Err(PlausibleError::Request(
+                "the event has no url, see `EventBuilder::context`".into(),
+            ));
+        }
+        if let Some(reason) = self.privacy_signal {
+            debug_warn!("Ignoring plausible event `{}`: {reason}", self.body.name);
+            return Err(PlausibleError::Ignored(reason));
+        }
+        if stored_opt_out() {
+            debug_warn!("Ignoring plausible event `{}`: opted out", self.body.name);
+            return Err(PlausibleError::Ignored(IgnoreReason::OptedOut));
+        }
+        if !self.config.allow_localhost {
+            if let Some(reason) = local_location(&self.body.url) {
+                debug_warn!("Ignoring plausible event `{}`: {reason}", self.body.name);
+                return Err(PlausibleError::Ignored(reason));
+            }
+        }
+        Ok(())
     }
 
     /// Creates a `spawn_local` thread and sends the event.
     ///
-    /// Use this function instead of [`send`] for simplicity
+    /// Use this function instead of [`send`](Self::send) for simplicity
     /// unless you want to do more things in the local thread.
+    /// Errors are passed to the [`on_error`](Self::on_error) callback if any.
+    ///
+    /// If the page is already hidden (e.g. the event is sent on `pagehide`)
+    /// it uses [`send_beacon`](Self::send_beacon) since the `spawn_local`
+    /// thread would not survive the unload. Outside the browser it uses it
+    /// too: leptos 0.7 renders without a tokio `LocalSet` to spawn it on, and
+    /// the server transport sends beacons in the background.
+    ///
+    /// With [`PlausibleConfig::queue`] set, events failing with a
+    /// [retryable](PlausibleError::is_retryable) error are queued instead of
+    /// reported. See [`crate::queue`]
     pub fn send_local(self) {
         debug_warn!("Preparing plausible event: `{:?}`", &self);
-        spawn_local(async move {
-            self.send().await;
-        });
+        let name = self.body.name.clone();
+        let on_error = self.on_error.clone();
+        let retry = self
+            .config
+            .queue
+            .clone()
+            .filter(|_| is_browser())
+            .map(|q| (q, self.config.endpoint(), self.body.clone()));
+        let report = move |res: Result<SendOutcome, PlausibleError>| {
+            if let Err(e) = res {
+                if let Some((queue, endpoint, body)) = retry.filter(|_| e.is_retryable()) {
+                    debug_warn!("Plausible event `{name}` queued for retry: {e}");
+                    queue::push(endpoint, body, &queue);
+                    return;
+                }
+                debug_warn!("Plausible event `{name}` not sent: {e}");
+                if let Some(OnError(on_error)) = on_error {
+                    on_error(e);
+                }
+            }
+        };
+
+        if !is_browser() || document().visibility_state() == VisibilityState::Hidden {
+            report(self.send_beacon());
+        } else {
+            spawn_local(async move {
+                report(self.send().await);
+            });
+        }
     }
 }
 
@@ -322,7 +725,7 @@ impl EventBuilder {
 /// Data structures such as objects, arrays etc. aren't accepted.
 // From https://github.com/goddtriffin/plausible-rs/ under MIT license
 // Implementation on how to constrain types easily from: https://stackoverflow.com/a/52582432/11767294
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum PropValue {
     // string
@@ -450,3 +853,79 @@ impl From<f64> for PropValue {
         Self::F64(f)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transport::RecordingTransport;
+
+    #[test]
+    fn builtin_event_with_explicit_context() {
+        let recorder = RecordingTransport::default();
+        let plausible = Plausible::new("example.com").with_transport(recorder.clone());
+        let context = PageContext::new("https://example.com/old-page?ref=mail")
+            .user_agent("Mozilla/5.0")
+            .ip("203.0.113.7");
+
+        let res = futures::executor::block_on(plausible.not_found().context(context).send());
+
+        assert_eq!(res, Ok(SendOutcome::Accepted));
+        let events = recorder.events();
+        let (_, payload, header) = &events[0];
+        assert_eq!(payload.name, "404");
+        assert_eq!(payload.url, "https://example.com/old-page?ref=mail");
+        assert_eq!(
+            payload.props.as_ref().and_then(|p| p.get("path")),
+            Some(&"/old-page".into())
+        );
+        assert_eq!(header.user_agent, "Mozilla/5.0");
+        assert_eq!(header.x_forwarded_for.as_deref(), Some("203.0.113.7"));
+    }
+
+    #[test]
+    fn event_with_explicit_context() {
+        let recorder = RecordingTransport::default();
+        let plausible =
+            Plausible::from(PlausibleConfig::new("example.com").default_prop("plan", "pro".into()))
+                .with_transport(recorder.clone());
+        let context = PageContext::new("https://example.com/api/signup")
+            .referrer("https://duckduckgo.com/")
+            .user_agent("Mozilla/5.0")
+            .ip("203.0.113.7");
+
+        let res =
+            futures::executor::block_on(plausible.event_with_context("Signup", context).send());
+
+        assert_eq!(res, Ok(SendOutcome::Accepted));
+        let events = recorder.events();
+        let (endpoint, payload, header) = &events[0];
+        assert_eq!(endpoint, "https://plausible.io/api/event");
+        assert_eq!(payload.name, "Signup");
+        assert_eq!(payload.url, "https://example.com/api/signup");
+        assert_eq!(payload.referrer.as_deref(), Some("https://duckduckgo.com/"));
+        assert_eq!(
+            payload.props.as_ref().and_then(|p| p.get("plan")),
+            Some(&"pro".into())
+        );
+        assert_eq!(header.user_agent, "Mozilla/5.0");
+        assert_eq!(header.x_forwarded_for.as_deref(), Some("203.0.113.7"));
+        // Nothing to take the page from
+        let res = futures::executor::block_on(
+            plausible
+                .event_with_context("Signup", PageContext::default())
+                .send(),
+        );
+        assert!(matches!(res, Err(PlausibleError::Request(_))));
+        assert_eq!(recorder.events().len(), 1);
+    }
+
+    #[test]
+    fn path_of_urls() {
+        assert_eq!(
+            url_path("https://example.com/blog/post?utm=x#top"),
+            "/blog/post"
+        );
+        assert_eq!(url_path("https://example.com"), "/");
+        assert_eq!(url_path("/relative/page?q=1"), "/relative/page");
+    }
+}
@@ -1,22 +1,32 @@
 //! Support for using AB tests (experiments) where the system randomly selects
 //! a given Variant to show the user.
 //!
-//!
-//!
-use std::collections::HashMap;
+//! The variant assigned to a visitor can be kept across page loads, see [`Sticky`],
+//! or derived from a stable `seed`, see [`Experiment::choose_for`]. The
+//! results can be analysed with [`stats`].
+
+// `#[component]` drops the attributes of the hidden `__Name` function it
+// generates, so the lints can't be allowed on each component
+#![allow(clippy::must_use_candidate, clippy::implicit_hasher)]
+pub mod stats;
+mod sticky;
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 
 use rand::distributions::WeightedIndex;
 use rand::prelude::*;
 
-use leptos::{
-    component, create_resource, store_value, use_context, view, ChildrenFn, IntoView, Provider,
-    Resource, Show, SignalGet, Suspense,
-};
+use leptos::context::Provider;
+use leptos::logging::debug_warn;
+use leptos::prelude::*;
 use serde::{Deserialize, Serialize};
 
-use crate::components::TrackElement;
+use crate::components::{use_tracking_allowed, TrackElement};
 use crate::event::PropValue;
 
+pub use sticky::Sticky;
+
 /// Represents the variant of an experiment with its custom name and weight
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Variant {
@@ -34,78 +44,251 @@ impl Variant {
     }
 }
 
-/// Define a new weighted A/B test
+/// Why the variants of an [`Experiment`] are not valid
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[allow(clippy::module_name_repetitions)]
+pub enum ExperimentError {
+    /// There are no variants
+    NoVariants,
+    /// All the variants have a weight of 0
+    ZeroWeight,
+    /// Two variants have the same name
+    DuplicateName(String),
+}
+
+impl fmt::Display for ExperimentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoVariants => write!(f, "the experiment has no variants"),
+            Self::ZeroWeight => write!(f, "the total weight of the variants is 0"),
+            Self::DuplicateName(name) => write!(f, "duplicated variant name `{name}`"),
+        }
+    }
+}
+
+impl std::error::Error for ExperimentError {}
+
+/// Define a new weighted A/B (or A/B/n) test
 ///
 /// This class is used to define the odds and pass it around
 /// as context so it gets propagated from (ideally) the top of the
 /// structure.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "ExperimentData")]
 pub struct Experiment {
     pub name: String,
-    a: Variant,
-    b: Variant,
-    /// Selected variant
+    variants: Vec<Variant>,
+    /// Index of the selected variant
     pub selected: usize,
+    #[serde(default)]
+    version: u32,
+    #[serde(default)]
+    sticky: Sticky,
+}
+
+/// Unvalidated [`Experiment`], as deserialized
+#[derive(Deserialize)]
+struct ExperimentData {
+    name: String,
+    variants: Vec<Variant>,
+    selected: usize,
+    #[serde(default)]
+    version: u32,
+    #[serde(default)]
+    sticky: Sticky,
+}
+
+impl TryFrom<ExperimentData> for Experiment {
+    type Error = ExperimentError;
+
+    fn try_from(data: ExperimentData) -> Result<Self, Self::Error> {
+        validate(&data.variants)?;
+        Ok(Self {
+            name: data.name,
+            variants: data.variants,
+            selected: data.selected,
+            version: data.version,
+            sticky: data.sticky,
+        })
+    }
+}
+
+fn validate(variants: &[Variant]) -> Result<(), ExperimentError> {
+    if variants.is_empty() {
+        return Err(ExperimentError::NoVariants);
+    }
+    if variants.iter().all(|v| v.weight == 0) {
+        return Err(ExperimentError::ZeroWeight);
+    }
+    let mut names = HashSet::new();
+    if let Some(v) = variants.iter().find(|v| !names.insert(v.name.as_str())) {
+        return Err(ExperimentError::DuplicateName(v.name.clone()));
+    }
+    Ok(())
 }
 
 impl Experiment {
-    #[must_use]
-    pub fn new(name: &str, a: Variant, b: Variant) -> Self {
-        Self {
-            a,
-            b,
+    /// A/B test with two variants.
+    ///
+    /// # Errors
+    ///
+    /// If both weights are 0 or both variants have the same name.
+    /// See [`ExperimentError`]
+    pub fn new(name: &str, a: Variant, b: Variant) -> Result<Self, ExperimentError> {
+        Self::with_variants(name, vec![a, b])
+    }
+
+    /// Test with any number of variants.
+    ///
+    /// # Errors
+    ///
+    /// If there are no variants, their total weight is 0 or two of them
+    /// have the same name. See [`ExperimentError`]
+    pub fn with_variants(name: &str, variants: Vec<Variant>) -> Result<Self, ExperimentError> {
+        validate(&variants)?;
+        Ok(Self {
             name: name.into(),
+            variants,
             selected: 0,
+            version: 0,
+            sticky: Sticky::default(),
+        })
+    }
+
+    /// Bump it when the experiment changes so visitors get new assignments
+    #[must_use]
+    pub const fn version(mut self, version: u32) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Where the assigned variant is kept, nowhere by default
+    #[must_use]
+    pub const fn sticky(mut self, sticky: Sticky) -> Self {
+        self.sticky = sticky;
+        self
+    }
+
+    #[must_use]
+    pub fn variants(&self) -> &[Variant] {
+        &self.variants
+    }
+
+    /// Cookie or `localStorage` key of the assigned variant
+    #[must_use]
+    pub fn storage_key(&self) -> String {
+        format!(
+            "plausible_exp_{}_v{}",
+            sticky::encode(&self.name),
+            self.version
+        )
+    }
+
+    /// Selects the variant called `name`. Returns `false`, leaving the
+    /// selection as it was, if there's none.
+    pub fn select(&mut self, name: &str) -> bool {
+        match self.variants.iter().position(|v| v.name == name) {
+            Some(i) => {
+                self.selected = i;
+                true
+            }
+            None => false,
         }
     }
 
     /// Choose the variant to show given the weights.
     ///
-    /// It's separated from the init since this needs to happen in a leptos' [`local_resource`]
+    /// It's separated from the init since this needs to happen in a leptos' resource
     /// to avoud hydration bugs
     pub fn choose(&mut self) {
         // Using RNG in SSR will cause hydration bugs unless it's within a `local_resource`
-        let weights: Vec<u16> = vec![self.a.weight, self.b.weight];
-        let dist = WeightedIndex::new(weights).expect("ERR in experiment");
-        let mut rng = thread_rng();
-        let res = dist.sample(&mut rng);
-        self.selected = res;
+        let weights = self.variants.iter().map(|v| v.weight);
+        // The constructors don't allow invalid weights, the first one otherwise
+        self.selected =
+            WeightedIndex::new(weights).map_or(0, |dist| dist.sample(&mut thread_rng()));
+    }
+
+    /// Chooses the variant given the weights from a hash of `seed` and the
+    /// experiment name, so a seed always gets the same variant, both on the
+    /// server and the browser. Use something stable like a user or session ID.
+    pub fn choose_for(&mut self, seed: &str) {
+        let total: u64 = self.variants.iter().map(|v| u64::from(v.weight)).sum();
+        self.selected = 0;
+        if total == 0 {
+            return;
+        }
+        let mut bucket = hash(&format!("{}:{seed}", self.name)) % total;
+        for (i, v) in self.variants.iter().enumerate() {
+            let weight = u64::from(v.weight);
+            if bucket < weight {
+                self.selected = i;
+                return;
+            }
+            bucket -= weight;
+        }
     }
 
     /// Returns the choosen variant
+    ///
+    /// # Panics
+    ///
+    /// If the experiment has no variants, which the constructors don't allow
     #[must_use]
-    pub const fn variant(&self) -> &Variant {
-        match self.selected {
-            0 => &self.a,
-            _ => &self.b,
-        }
+    pub fn variant(&self) -> &Variant {
+        self.variants
+            .get(self.selected)
+            .or_else(|| self.variants.first())
+            .expect("experiments have at least one variant")
     }
 }
 
+/// FNV-1a with a final mix so the low bits are spread too. Unlike the std
+/// hashers it's the same across platforms and Rust versions.
+fn hash(value: &str) -> u64 {
+    let mut h = value.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |h, b| {
+        (h ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    });
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    h ^= h >> 33;
+    h
+}
+
 impl Default for Experiment {
     /// Inits a default experiment with A and B variants with the same weight.
     fn default() -> Self {
         Self {
-            name: String::from("Experiment"),
-            a: Variant::new("A", 1),
-            b: Variant::new("B", 1),
+            name: "Experiment".into(),
+            variants: vec![Variant::new("A", 1), Variant::new("B", 1)],
             selected: 0,
+            version: 0,
+            sticky: Sticky::default(),
         }
     }
 }
 
-/// A component that will show the choosen variant of the experiment.
+/// A component that will show the choosen variant of an A/B experiment:
+/// `a` for the first variant and the children for the second.
+/// Experiments with more variants must use [`ExperimentVariants`], since
+/// the children would be shown for all of them.
 ///
 /// It will always send an event called `ExperimentView` when any of the
 /// variants appears in the viewport (see
 /// [`leptos_use::use_element_visibility`](https://leptos-use.rs/elements/use_element_visibility.html))
 /// and will provide the experiment as context to be used by the downstream events.
 ///
+/// With `ssr` the variant is chosen on the server and sent along the page so
+/// `hydrate` shows the same one. With `csr` it's chosen in the browser.
+/// With [`Experiment::sticky`] returning visitors get the variant they were assigned before.
+///
+/// With a `seed` the variant is chosen right away with
+/// [`Experiment::choose_for`] instead, so it's rendered on the server without
+/// waiting for a resource and nothing is stored.
+///
 /// ```rust
-/// # use leptos::*;
-/// # use plaicards::web::plausible::experiments::{Variant, Experiment, ExperimentView};
-/// # let runtime = create_runtime();
-/// # // create_runtime fails when trying to use create_resource
+/// # use leptos::prelude::*;
+/// # use leptos_plausible::experiments::{Variant, Experiment, ExperimentView};
+/// # // Rendering needs the plausible context
 /// # if false {
 ///
 /// #[component]
@@ -121,7 +304,8 @@ impl Default for Experiment {
 ///     "Experiment",
 ///     Variant::new("A", 1),
 ///     Variant::new("B", 9)
-/// );
+/// )
+/// .expect("valid experiment");
 ///
 /// view! {
 ///   <ExperimentView
@@ -133,7 +317,6 @@ impl Default for Experiment {
 /// }
 /// # ;
 /// # }
-/// # runtime.dispose();
 /// ```
 #[must_use]
 #[component]
@@ -143,24 +326,26 @@ pub fn ExperimentView<F, IV>(
     //#[prop(into)] b: F,
     a: F,
     children: ChildrenFn,
+    /// Stable key to choose the variant deterministically
+    #[prop(optional, into)]
+    seed: Option<String>,
 ) -> impl IntoView
 where
-    F: Fn() -> IV + 'static,
-    IV: IntoView,
+    F: Fn() -> IV + Send + Sync + 'static,
+    IV: IntoView + 'static,
 {
-    let exp = store_value(exp);
-    let variant = create_resource(
-        || (),
-        move |()| async move {
-            let mut e = exp.get_value();
-            e.choose();
-            e
-        },
-    );
+    if exp.variants().len() != 2 {
+        debug_warn!(
+            "ExperimentView only renders 2 variants but `{}` has {}, use ExperimentVariants",
+            exp.name,
+            exp.variants().len()
+        );
+    }
+    let variant = choose_variant(exp, seed);
 
     // Store the views so we can "Copy" its references within other components
-    let a = store_value(a);
-    let b = store_value(children);
+    let a = StoredValue::new(a);
+    let b = StoredValue::new(children);
 
     view! {
         <Provider value=ExperimentCtx(variant)>
@@ -169,7 +354,7 @@ where
             <Suspense fallback=|| ()>
                 <TrackElement name="ExperimentView"/>
                 <Show
-                    when=move || variant.get().map_or_else(|| false, |v| v.selected == 1)
+                    when=move || variant.get().map_or_else(|| false, |v| v.selected != 0)
                     fallback=move || a.with_value(|a| a())
                 >
                     {b.with_value(|b| b())}
@@ -179,6 +364,106 @@ where
     }
 }
 
+/// Variant chosen from the `seed`, or stored for the visitor, or chosen once
+/// per render. See [`Experiment::choose_for`] and [`Experiment::choose`]
+fn choose_variant(exp: Experiment, seed: Option<String>) -> Signal<Option<Experiment>> {
+    if let Some(seed) = seed {
+        let mut e = exp;
+        e.choose_for(&seed);
+        let e = StoredValue::new(e);
+        return Signal::derive(move || Some(e.get_value()));
+    }
+    // Read now, the context of the request is gone inside the resource
+    let stored = sticky::load(&exp);
+    let local = exp.sticky == Sticky::LocalStorage;
+    let exp = StoredValue::new(exp);
+    let fetcher = move |()| {
+        let stored = stored.clone();
+        async move {
+            let mut e = exp.get_value();
+            if !stored.is_some_and(|name| e.select(&name)) {
+                e.choose();
+            }
+            e
+        }
+    };
+    let variant = if local {
+        let variant = LocalResource::new(move || fetcher(()));
+        Signal::derive(move || variant.get().map(|e| e.take()))
+    } else {
+        let variant = Resource::new(|| (), fetcher);
+        Signal::derive(move || variant.get())
+    };
+    let tracking_allowed = use_tracking_allowed();
+    // Also when the variant comes from the server
+    Effect::new(move |_| {
+        if !tracking_allowed.get() {
+            return;
+        }
+        if let Some(e) = variant.get() {
+            sticky::save(&e);
+        }
+    });
+    variant
+}
+
+/// Like [`ExperimentView`] for any number of variants, showing the view of
+/// `views` with the name of the chosen variant. It also takes a `seed`.
+///
+/// ```rust
+/// # use leptos::prelude::*;
+/// # use leptos_plausible::experiments::{Variant, Experiment, ExperimentVariants};
+/// # use std::collections::HashMap;
+/// # if false {
+/// let e = Experiment::with_variants(
+///     "Pricing",
+///     vec![Variant::new("A", 1), Variant::new("B", 1), Variant::new("C", 2)],
+/// )
+/// .expect("valid experiment");
+///
+/// let views: HashMap<String, ViewFn> = HashMap::from([
+///     ("A".into(), (|| view! { <p>"Monthly"</p> }).into()),
+///     ("B".into(), (|| view! { <p>"Yearly"</p> }).into()),
+///     ("C".into(), (|| view! { <p>"Lifetime"</p> }).into()),
+/// ]);
+///
+/// view! { <ExperimentVariants exp=e views=views/> }
+/// # ;
+/// # }
+/// ```
+#[must_use]
+#[component]
+pub fn ExperimentVariants(
+    exp: Experiment,
+    views: HashMap<String, ViewFn>,
+    /// Stable key to choose the variant deterministically
+    #[prop(optional, into)]
+    seed: Option<String>,
+) -> impl IntoView {
+    if let Some(v) = exp.variants().iter().find(|v| !views.contains_key(&v.name)) {
+        debug_warn!(
+            "No view for variant `{}` of experiment `{}`",
+            v.name,
+            exp.name
+        );
+    }
+    let variant = choose_variant(exp, seed);
+    let views = StoredValue::new(views);
+
+    view! {
+        <Provider value=ExperimentCtx(variant)>
+            <Suspense fallback=|| ()>
+                <TrackElement name="ExperimentView"/>
+                {move || {
+                    variant
+                        .get()
+                        .and_then(|e| views.with_value(|v| v.get(&e.variant().name).map(ViewFn::run)))
+                }}
+            </Suspense>
+        </Provider>
+    }
+}
+
 /// Retrieve experiment from leptos context.
 ///
 #[must_use]
@@ -192,7 +477,7 @@ pub fn use_experiment_props() -> Option<HashMap<String, PropValue>> {
 }
 
 #[derive(Copy, Clone, Debug)]
-pub struct ExperimentCtx(pub Resource<(), Experiment>);
+pub struct ExperimentCtx(pub Signal<Option<Experiment>>);
 
 impl ExperimentCtx {
     pub fn to_plausible(&self) -> HashMap<String, PropValue> {
@@ -220,6 +505,7 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::unnecessary_operation)]
     fn build_variant() {
         Variant {
             name: String::from("A"),
@@ -228,8 +514,84 @@ mod test {
     }
 
     #[test]
+    fn validate_variants() {
+        assert_eq!(
+            Experiment::with_variants("E", vec![]).err(),
+            Some(ExperimentError::NoVariants)
+        );
+        assert_eq!(
+            Experiment::with_variants("E", vec![Variant::new("A", 0), Variant::new("B", 0)]).err(),
+            Some(ExperimentError::ZeroWeight)
+        );
+        assert_eq!(
+            Experiment::with_variants("E", vec![Variant::new("A", 1), Variant::new("A", 2)]).err(),
+            Some(ExperimentError::DuplicateName("A".into()))
+        );
+        assert_eq!(
+            Experiment::new("E", Variant::new("A", 0), Variant::new("B", 0)).err(),
+            Some(ExperimentError::ZeroWeight)
+        );
+        assert!(serde_json::from_str::<Experiment>(
+            r#"{"name": "E", "variants": [], "selected": 0}"#
+        )
+        .is_err());
+
+        let mut e = Experiment::with_variants(
+            "E",
+            vec![
+                Variant::new("A", 0),
+                Variant::new("B", 0),
+                Variant::new("C", 1),
+            ],
+        )
+        .expect("valid experiment");
+        e.choose();
+        assert_eq!(e.variant().name, "C");
+    }
+
+    #[test]
+    fn select_stored_variant() {
+        let mut e = Experiment::new("Pricing page", Variant::new("A", 1), Variant::new("B", 1))
+            .expect("valid experiment");
+        assert_eq!(e.storage_key(), "plausible_exp_Pricing%20page_v0");
+        assert_eq!(
+            e.clone().version(2).storage_key(),
+            "plausible_exp_Pricing%20page_v2"
+        );
+
+        assert!(e.select("B"));
+        assert_eq!(e.selected, 1);
+        // Removed variants are chosen again
+        assert!(!e.select("C"));
+        assert_eq!(e.selected, 1);
+    }
+
+    #[test]
+    fn seeded_experiments() {
+        let mut e = Experiment::new("Experiment", Variant::new("A", 1), Variant::new("B", 9))
+            .expect("valid experiment");
+        let choices: Vec<usize> = (0..1000)
+            .map(|i| {
+                e.choose_for(&format!("user-{i}"));
+                e.selected
+            })
+            .collect();
+        let a_count = choices.iter().filter(|v| **v == 0).count();
+        assert!((50..=150).contains(&a_count), "Weights do not seem to work");
+
+        // Always the same for a seed
+        for (i, selected) in choices.iter().enumerate().take(20) {
+            e.choose_for(&format!("user-{i}"));
+            assert_eq!(e.selected, *selected);
+        }
+        assert_eq!(hash("Experiment:user-1"), 0x5b10_0d80_97ce_9871);
+    }
+
+    #[test]
+    #[allow(clippy::uninlined_format_args, clippy::manual_range_contains)]
     fn weighted_experiments() {
-        let mut e = Experiment::new("Experiment", Variant::new("A", 1), Variant::new("B", 9));
+        let mut e = Experiment::new("Experiment", Variant::new("A", 1), Variant::new("B", 9))
+            .expect("valid experiment");
 
         let choices: Vec<usize> = (0..1000)
             .map(|_| {
@@ -0,0 +1,478 @@
+//! Statistics to analyse the results of an [`Experiment`](super::Experiment)
+//! and plan its size.
+//!
+//! Give it the exposures (e.g. `ExperimentView` events) and conversions (e.g.
+//! goal events with the same `exp_` property) of each variant, taken from your
+//! Plausible instance.
+//!
+//! ```rust
+//! use leptos_plausible::experiments::stats::{Comparison, VariantStats};
+//!
+//! let control = VariantStats::new(1000, 100).expect("valid stats");
+//! let variant = VariantStats::new(1000, 130).expect("valid stats");
+//! let result = Comparison::new(control, variant, 0.95).expect("enough data");
+//! assert!(result.p_value < 0.05);
+//! assert!(result.probability_to_beat > 0.95);
+//! ```
+use std::f64::consts::SQRT_2;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// Why some [`VariantStats`] are not valid
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::module_name_repetitions)]
+pub enum StatsError {
+    /// There are more conversions than exposures
+    TooManyConversions { exposures: u64, conversions: u64 },
+}
+
+impl fmt::Display for StatsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooManyConversions {
+                exposures,
+                conversions,
+            } => write!(
+                f,
+                "{conversions} conversions out of only {exposures} exposures"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for StatsError {}
+
+/// Exposures and conversions of a variant
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "StatsData")]
+#[allow(clippy::module_name_repetitions)]
+pub struct VariantStats {
+    exposures: u64,
+    conversions: u64,
+}
+
+/// Unvalidated [`VariantStats`], as deserialized
+#[derive(Deserialize)]
+struct StatsData {
+    exposures: u64,
+    conversions: u64,
+}
+
+impl TryFrom<StatsData> for VariantStats {
+    type Error = StatsError;
+
+    fn try_from(data: StatsData) -> Result<Self, Self::Error> {
+        Self::new(data.exposures, data.conversions)
+    }
+}
+
+impl VariantStats {
+    /// # Errors
+    ///
+    /// If there are more `conversions` than `exposures`
+    pub const fn new(exposures: u64, conversions: u64) -> Result<Self, StatsError> {
+        if conversions > exposures {
+            return Err(StatsError::TooManyConversions {
+                exposures,
+                conversions,
+            });
+        }
+        Ok(Self {
+            exposures,
+            conversions,
+        })
+    }
+
+    #[must_use]
+    pub const fn exposures(&self) -> u64 {
+        self.exposures
+    }
+
+    #[must_use]
+    pub const fn conversions(&self) -> u64 {
+        self.conversions
+    }
+
+    /// Conversions per exposure, 0 without exposures
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn conversion_rate(&self) -> f64 {
+        if self.exposures == 0 {
+            0.0
+        } else {
+            self.conversions as f64 / self.exposures as f64
+        }
+    }
+
+    /// Wilson score interval of the conversion rate. `None` without exposures
+    /// or if `confidence` is not between 0 and 1.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn confidence_interval(&self, confidence: f64) -> Option<(f64, f64)> {
+        if self.exposures == 0 {
+            return None;
+        }
+        let z = z_score(confidence)?;
+        let n = self.exposures as f64;
+        let p = self.conversion_rate();
+        let z2 = z * z;
+        let center = (p + z2 / (2.0 * n)) / (1.0 + z2 / n);
+        let half = z / (1.0 + z2 / n) * (p * (1.0 - p) / n + z2 / (4.0 * n * n)).sqrt();
+        Some(((center - half).max(0.0), (center + half).min(1.0)))
+    }
+
+    /// Variance of the conversion rate
+    #[allow(clippy::cast_precision_loss)]
+    fn variance(&self) -> f64 {
+        let p = self.conversion_rate();
+        p * (1.0 - p) / self.exposures as f64
+    }
+}
+
+/// Relative change of the conversion rate of `variant` over `control`, e.g.
+/// `0.3` for a 30% increase. `None` if `control` has no conversions.
+#[must_use]
+pub fn uplift(control: &VariantStats, variant: &VariantStats) -> Option<f64> {
+    let base = control.conversion_rate();
+    (base > 0.0).then(|| (variant.conversion_rate() - base) / base)
+}
+
+/// Two sided p-value of a pooled two-proportion z-test. `None` without
+/// exposures or if every exposure (or none) converted.
+#[must_use]
+#[allow(clippy::cast_precision_loss)]
+pub fn p_value(control: &VariantStats, variant: &VariantStats) -> Option<f64> {
+    if control.exposures == 0 || variant.exposures == 0 {
+        return None;
+    }
+    let (n1, n2) = (control.exposures as f64, variant.exposures as f64);
+    let pooled = (control.conversions + variant.conversions) as f64 / (n1 + n2);
+    let se = (pooled * (1.0 - pooled) * (1.0 / n1 + 1.0 / n2)).sqrt();
+    if se == 0.0 {
+        return None;
+    }
+    let z = (variant.conversion_rate() - control.conversion_rate()) / se;
+    Some(erfc(z.abs() / SQRT_2))
+}
+
+/// Interval of the difference between the conversion rates of `variant` and
+/// `control`. `None` without exposures or if `confidence` is not between 0
+/// and 1.
+#[must_use]
+pub fn difference_interval(
+    control: &VariantStats,
+    variant: &VariantStats,
+    confidence: f64,
+) -> Option<(f64, f64)> {
+    if control.exposures == 0 || variant.exposures == 0 {
+        return None;
+    }
+    let z = z_score(confidence)?;
+    let diff = variant.conversion_rate() - control.conversion_rate();
+    let half = z * (control.variance() + variant.variance()).sqrt();
+    Some((diff - half, diff + half))
+}
+
+/// Probability that the conversion rate of `variant` is higher than the one
+/// of `control`, with uniform `Beta(1, 1)` priors.
+///
+/// Computed exactly, it takes as many steps as conversions of `variant`.
+#[must_use]
+#[allow(clippy::cast_precision_loss)]
+pub fn probability_to_beat(control: &VariantStats, variant: &VariantStats) -> f64 {
+    let alpha_a = (control.conversions + 1) as f64;
+    let beta_a = (control.exposures.saturating_sub(control.conversions) + 1) as f64;
+    let beta_b = (variant.exposures.saturating_sub(variant.conversions) + 1) as f64;
+    let base = ln_beta(alpha_a, beta_a);
+    let total: f64 = (0..=variant.conversions)
+        .map(|i| {
+            let i = i as f64;
+            (ln_beta(alpha_a + i, beta_a + beta_b)
+                - (beta_b + i).ln()
+                - ln_beta(1.0 + i, beta_b)
+                - base)
+                .exp()
+        })
+        .sum();
+    total.clamp(0.0, 1.0)
+}
+
+/// Summary of a variant against the control
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Comparison {
+    pub control_rate: f64,
+    pub variant_rate: f64,
+    /// See [`uplift`]
+    pub uplift: Option<f64>,
+    /// See [`p_value`]
+    pub p_value: f64,
+    /// See [`probability_to_beat`]
+    pub probability_to_beat: f64,
+    /// See [`difference_interval`]
+    pub difference_interval: (f64, f64),
+}
+
+impl Comparison {
+    /// `None` without exposures, if every exposure (or none) converted or if
+    /// `confidence` is not between 0 and 1.
+    #[must_use]
+    pub fn new(control: VariantStats, variant: VariantStats, confidence: f64) -> Option<Self> {
+        Some(Self {
+            control_rate: control.conversion_rate(),
+            variant_rate: variant.conversion_rate(),
+            uplift: uplift(&control, &variant),
+            p_value: p_value(&control, &variant)?,
+            probability_to_beat: probability_to_beat(&control, &variant),
+            difference_interval: difference_interval(&control, &variant, confidence)?,
+        })
+    }
+
+    /// Whether the difference is significant at `alpha`, e.g. `0.05`
+    #[must_use]
+    pub fn is_significant(&self, alpha: f64) -> bool {
+        self.p_value < alpha
+    }
+}
+
+/// Exposures needed per variant to detect a change of the conversion rate.
+///
+/// The change is relative to the `baseline` rate, e.g. `0.1` for 10%, and
+/// it's detected by a two sided test at `alpha` with the given `power`,
+/// usually `0.05` and `0.8`. `None` if any of them is out of range.
+#[must_use]
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub fn sample_size(baseline: f64, min_effect: f64, alpha: f64, power: f64) -> Option<u64> {
+    let (p1, p2) = rates(baseline, min_effect)?;
+    let z_alpha = z_score(1.0 - alpha)?;
+    let z_beta = quantile(power)?;
+    let (pooled_sd, sd) = deviations(p1, p2);
+    let n = ((z_alpha * pooled_sd + z_beta * sd) / (p2 - p1)).powi(2);
+    Some(n.ceil() as u64)
+}
+
+/// Probability of detecting a relative change of `min_effect` over the
+/// `baseline` conversion rate with `exposures` per variant, see
+/// [`sample_size`].
+#[must_use]
+#[allow(clippy::cast_precision_loss)]
+pub fn power(baseline: f64, min_effect: f64, exposures: u64, alpha: f64) -> Option<f64> {
+    let (p1, p2) = rates(baseline, min_effect)?;
+    let z_alpha = z_score(1.0 - alpha)?;
+    let (pooled_sd, sd) = deviations(p1, p2);
+    let z = (p2 - p1)
+        .abs()
+        .mul_add((exposures as f64).sqrt(), -z_alpha * pooled_sd)
+        / sd;
+    Some(normal_cdf(z))
+}
+
+/// Conversion rates of the control and the variant
+fn rates(baseline: f64, min_effect: f64) -> Option<(f64, f64)> {
+    let p2 = baseline * (1.0 + min_effect);
+    let valid = |p: f64| p > 0.0 && p < 1.0;
+    (valid(baseline) && valid(p2) && min_effect != 0.0).then_some((baseline, p2))
+}
+
+/// Standard deviations under the null hypothesis and the alternative one
+// `f64::midpoint` needs Rust 1.85
+#[allow(clippy::manual_midpoint)]
+fn deviations(p1: f64, p2: f64) -> (f64, f64) {
+    let pooled = (p1 + p2) / 2.0;
+    (
+        (2.0 * pooled * (1.0 - pooled)).sqrt(),
+        p1.mul_add(1.0 - p1, p2 * (1.0 - p2)).sqrt(),
+    )
+}
+
+/// Two sided critical value, e.g. 1.96 for `0.95`
+fn z_score(confidence: f64) -> Option<f64> {
+    quantile(1.0 - (1.0 - confidence) / 2.0).filter(|_| confidence > 0.0 && confidence < 1.0)
+}
+
+fn normal_cdf(x: f64) -> f64 {
+    0.5 * erfc(-x / SQRT_2)
+}
+
+/// Complementary error function, with a relative error under 1.2e-7
+/// (Numerical Recipes)
+#[allow(clippy::suboptimal_flops)]
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let poly = [
+        -1.265_512_23,
+        1.000_023_68,
+        0.374_091_96,
+        0.096_784_18,
+        -0.186_288_06,
+        0.278_868_07,
+        -1.135_203_98,
+        1.488_515_87,
+        -0.822_152_23,
+        0.170_872_77,
+    ]
+    .iter()
+    .rev()
+    .fold(0.0, |acc, c| c + t * acc);
+    let res = t * (-z * z + poly).exp();
+    if x >= 0.0 {
+        res
+    } else {
+        2.0 - res
+    }
+}
+
+/// Inverse of the standard normal CDF, with a relative error under 1.2e-9
+/// (Acklam's algorithm). `None` unless `p` is between 0 and 1.
+#[allow(clippy::suboptimal_flops)]
+fn quantile(p: f64) -> Option<f64> {
+    const A: [f64; 6] = [
+        -3.969_683_028_665_376e1,
+        2.209_460_984_245_205e2,
+        -2.759_285_104_469_687e2,
+        1.383_577_518_672_69e2,
+        -3.066_479_806_614_716e1,
+        2.506_628_277_459_239,
+    ];
+    const B: [f64; 5] = [
+        -5.447_609_879_822_406e1,
+        1.615_858_368_580_409e2,
+        -1.556_989_798_598_866e2,
+        6.680_131_188_771_972e1,
+        -1.328_068_155_288_572e1,
+    ];
+    const C: [f64; 6] = [
+        -7.784_894_002_430_293e-3,
+        -3.223_964_580_411_365e-1,
+        -2.400_758_277_161_838,
+        -2.549_732_539_343_734,
+        4.374_664_141_464_968,
+        2.938_163_982_698_783,
+    ];
+    const D: [f64; 4] = [
+        7.784_695_709_041_462e-3,
+        3.224_671_290_700_398e-1,
+        2.445_134_137_142_996,
+        3.754_408_661_907_416,
+    ];
+    const LOW: f64 = 0.024_25;
+
+    if !(p > 0.0 && p < 1.0) {
+        return None;
+    }
+    let poly = |coefs: &[f64], x: f64| coefs.iter().fold(0.0, |acc, c| acc * x + c);
+    let tail = |q: f64| poly(&C, q) / (poly(&D, q) * q + 1.0);
+    Some(if p < LOW {
+        tail((-2.0 * p.ln()).sqrt())
+    } else if p > 1.0 - LOW {
+        -tail((-2.0 * (1.0 - p).ln()).sqrt())
+    } else {
+        let q = p - 0.5;
+        let r = q * q;
+        poly(&A, r) * q / (poly(&B, r) * r + 1.0)
+    })
+}
+
+/// Logarithm of the gamma function for positive `x` (Lanczos approximation)
+#[allow(clippy::suboptimal_flops, clippy::cast_precision_loss)]
+fn ln_gamma(x: f64) -> f64 {
+    const G: f64 = 7.0;
+    const COEFS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+    let x = x - 1.0;
+    let sum = COEFS
+        .iter()
+        .enumerate()
+        .skip(1)
+        .fold(COEFS[0], |acc, (i, c)| acc + c / (x + i as f64));
+    let t = x + G + 0.5;
+    0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + sum.ln()
+}
+
+fn ln_beta(a: f64, b: f64) -> f64 {
+    ln_gamma(a) + ln_gamma(b) - ln_gamma(a + b)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn stats(exposures: u64, conversions: u64) -> VariantStats {
+        VariantStats::new(exposures, conversions).expect("valid stats")
+    }
+
+    fn assert_close(value: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (value - expected).abs() < tolerance,
+            "{value} is not {expected}"
+        );
+    }
+
+    #[test]
+    fn rates_and_intervals() {
+        assert_eq!(
+            VariantStats::new(10, 11),
+            Err(StatsError::TooManyConversions {
+                exposures: 10,
+                conversions: 11
+            })
+        );
+        let control = stats(100, 10);
+        assert_close(control.conversion_rate(), 0.1, 1e-12);
+        let (low, high) = control.confidence_interval(0.95).expect("interval");
+        assert_close(low, 0.055_229, 1e-5);
+        assert_close(high, 0.174_366, 1e-5);
+        assert_eq!(stats(0, 0).confidence_interval(0.95), None);
+        assert_eq!(control.confidence_interval(1.0), None);
+        assert!(
+            serde_json::from_str::<VariantStats>(r#"{"exposures": 10, "conversions": 11}"#)
+                .is_err()
+        );
+
+        assert_close(
+            uplift(&control, &stats(100, 13)).expect("uplift"),
+            0.3,
+            1e-12,
+        );
+        assert_eq!(uplift(&stats(100, 0), &control), None);
+    }
+
+    #[test]
+    fn frequentist_and_bayesian_tests() {
+        let control = stats(1000, 100);
+        let variant = stats(1000, 130);
+        assert_close(
+            p_value(&control, &variant).expect("p-value"),
+            0.035_49,
+            1e-4,
+        );
+        assert_close(probability_to_beat(&control, &variant), 0.982_17, 1e-4);
+        assert_close(probability_to_beat(&control, &control), 0.5, 1e-6);
+        assert_eq!(p_value(&stats(10, 0), &stats(10, 0)), None);
+
+        let result = Comparison::new(control, variant, 0.95).expect("comparison");
+        assert!(result.is_significant(0.05));
+        let (low, high) = result.difference_interval;
+        assert!(low > 0.0 && high < 0.06 && low < 0.03 && high > 0.03);
+    }
+
+    #[test]
+    fn planning() {
+        assert_eq!(sample_size(0.1, 0.1, 0.05, 0.8), Some(14_751));
+        assert_close(power(0.1, 0.1, 14_751, 0.05).expect("power"), 0.8, 1e-3);
+        assert_eq!(sample_size(0.0, 0.1, 0.05, 0.8), None);
+        assert_eq!(sample_size(0.1, 0.1, 0.05, 1.0), None);
+        assert_close(quantile(0.975).expect("quantile"), 1.959_964, 1e-6);
+        assert_close(normal_cdf(1.959_964), 0.975, 1e-6);
+    }
+}
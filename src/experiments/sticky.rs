@@ -0,0 +1,164 @@
+//! Keeps the variant assigned to a visitor across page loads.
+//!
+//! The name of the variant is stored under [`Experiment::storage_key`], so
+//! changing the [`Experiment::version`] starts over with new assignments.
+//! Nothing is stored unless tracking is allowed, see
+//! [`use_tracking_allowed`](crate::components::use_tracking_allowed).
+use js_sys::Reflect;
+use leptos::prelude::document;
+use serde::{Deserialize, Serialize};
+
+use super::Experiment;
+use crate::is_browser;
+use crate::privacy::local_storage;
+
+/// A year, in seconds
+const COOKIE_MAX_AGE: u32 = 31_536_000;
+
+/// Where the variant assigned to a visitor is kept
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Sticky {
+    /// Chosen again on every page load
+    #[default]
+    Off,
+    /// First party cookie kept for a year. With `ssr` it's also read on the
+    /// server, so the same variant is rendered there.
+    Cookie,
+    /// `localStorage`. The variant is chosen in the browser only, so nothing
+    /// is rendered on the server.
+    LocalStorage,
+}
+
+/// Name of the variant stored for `exp`, if any
+#[must_use]
+pub fn load(exp: &Experiment) -> Option<String> {
+    let key = exp.storage_key();
+    match exp.sticky {
+        Sticky::Off => None,
+        Sticky::Cookie => cookie_value(&cookies()?, &key),
+        Sticky::LocalStorage => local_storage()?.get_item(&key).ok().flatten(),
+    }
+}
+
+/// Stores the selected variant of `exp`. Only works in the browser.
+pub fn save(exp: &Experiment) {
+    if !is_browser() {
+        return;
+    }
+    let key = exp.storage_key();
+    let name = &exp.variant().name;
+    match exp.sticky {
+        Sticky::Off => {}
+        Sticky::Cookie => {
+            let cookie = format!(
+                "{key}={}; Path=/; Max-Age={COOKIE_MAX_AGE}; SameSite=Lax",
+                encode(name)
+            );
+            let _ = Reflect::set(&document(), &"cookie".into(), &cookie.into());
+        }
+        Sticky::LocalStorage => {
+            if let Some(storage) = local_storage() {
+                let _ = storage.set_item(&key, name);
+            }
+        }
+    }
+}
+
+/// `document.cookie`, or the `Cookie` header of the request being served
+fn cookies() -> Option<String> {
+    if is_browser() {
+        return Reflect::get(&document(), &"cookie".into())
+            .ok()?
+            .as_string();
+    }
+    #[cfg(feature = "actix")]
+    if let Some(req) = leptos::prelude::use_context::<leptos_actix::Request>() {
+        let headers = req.headers().get_all(actix_web::http::header::COOKIE);
+        return Some(join_cookies(headers.filter_map(|h| h.to_str().ok())));
+    }
+    #[cfg(feature = "ssr")]
+    if let Some(parts) = leptos::prelude::use_context::<http::request::Parts>() {
+        let headers = parts.headers.get_all(http::header::COOKIE);
+        return Some(join_cookies(headers.iter().filter_map(|h| h.to_str().ok())));
+    }
+    None
+}
+
+#[cfg(feature = "ssr")]
+fn join_cookies<'a>(headers: impl Iterator<Item = &'a str>) -> String {
+    headers.collect::<Vec<_>>().join("; ")
+}
+
+/// Value of the cookie `key` in a `Cookie` header or `document.cookie`
+#[must_use]
+pub fn cookie_value(cookies: &str, key: &str) -> Option<String> {
+    cookies
+        .split(';')
+        .filter_map(|c| c.trim().split_once('='))
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| decode(v))
+}
+
+/// Percent-encodes everything but ASCII letters, digits, `-`, `_` and `.`
+#[must_use]
+pub fn encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| {
+            if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.') {
+                char::from(b).to_string()
+            } else {
+                format!("%{b:02X}")
+            }
+        })
+        .collect()
+}
+
+#[must_use]
+pub fn decode(value: &str) -> String {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        let hex = tail
+            .get(..2)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match hex {
+            Some(decoded) if b == b'%' => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(b);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encoded_names() {
+        assert_eq!(encode("Pricing page"), "Pricing%20page");
+        assert_eq!(encode("a;b=c"), "a%3Bb%3Dc");
+        for name in ["Pricing page", "ñandú", "100%", "a;b=c", "%zz"] {
+            assert_eq!(decode(&encode(name)), name);
+        }
+        assert_eq!(decode("100%"), "100%");
+    }
+
+    #[test]
+    fn cookie_values() {
+        let cookies = "theme=dark; plausible_exp_Pricing_v1=Yearly%20plan;other=1";
+        assert_eq!(
+            cookie_value(cookies, "plausible_exp_Pricing_v1").as_deref(),
+            Some("Yearly plan")
+        );
+        assert_eq!(cookie_value(cookies, "plausible_exp_Pricing_v2"), None);
+        assert_eq!(cookie_value("", "theme"), None);
+    }
+}
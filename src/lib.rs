@@ -1,5 +1,29 @@
 pub mod components;
+mod config;
+pub mod dispatch;
+pub mod engagement;
+mod error;
 mod event;
 pub mod experiments;
+mod privacy;
+#[cfg(any(feature = "axum", feature = "actix"))]
+pub mod proxy;
+pub mod queue;
+#[cfg(feature = "ssr")]
+pub mod server;
+pub mod transport;
 
-pub use event::Plausible;
+pub use config::PlausibleConfig;
+pub use error::{PlausibleError, SendOutcome};
+pub use event::{
+    EventBuilder, EventName, PageContext, Plausible, PlausibleHeader, PlausiblePayload, PropValue,
+    RevenueValue,
+};
+pub use privacy::IgnoreReason;
+
+/// Whether the code runs in the browser: a wasm build that is not the `ssr` one.
+///
+/// Leptos 0.7 no longer provides it.
+pub(crate) const fn is_browser() -> bool {
+    cfg!(all(target_arch = "wasm32", not(feature = "ssr")))
+}
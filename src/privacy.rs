@@ -0,0 +1,159 @@
+//! Rules deciding when events must not leave the browser
+use js_sys::Reflect;
+use leptos::prelude::window;
+use std::fmt;
+use web_sys::Storage;
+
+use crate::is_browser;
+
+/// `localStorage` key honoured by the official script to opt out
+pub const OPT_OUT_KEY: &str = "plausible_ignore";
+
+/// Why an event was ignored instead of sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IgnoreReason {
+    /// The page is served from `localhost`, a loopback address or a `.local` host.
+    /// See [`PlausibleConfig::allow_localhost`](crate::PlausibleConfig::allow_localhost)
+    Localhost,
+    /// The page is opened from the file system
+    FileProtocol,
+    /// The visitor opted out. See [`Plausible::opt_out`](crate::Plausible::opt_out)
+    OptedOut,
+    /// The browser sends `navigator.doNotTrack`.
+    /// See [`PlausibleConfig::respect_privacy_signals`](crate::PlausibleConfig::respect_privacy_signals)
+    DoNotTrack,
+    /// The browser sends `navigator.globalPrivacyControl`.
+    /// See [`PlausibleConfig::respect_privacy_signals`](crate::PlausibleConfig::respect_privacy_signals)
+    GlobalPrivacyControl,
+    /// Too many events with the same name were sent in the last minute.
+    /// See [`DispatchConfig`](crate::dispatch::DispatchConfig)
+    RateLimited,
+}
+
+impl fmt::Display for IgnoreReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Localhost => write!(f, "localhost"),
+            Self::FileProtocol => write!(f, "file:// protocol"),
+            Self::OptedOut => write!(f, "{OPT_OUT_KEY} is set in localStorage"),
+            Self::DoNotTrack => write!(f, "Do Not Track is enabled"),
+            Self::GlobalPrivacyControl => write!(f, "Global Privacy Control is enabled"),
+            Self::RateLimited => write!(f, "rate limit reached"),
+        }
+    }
+}
+
+/// [`local_page`] of the location open in the browser, since the url of an
+/// event may be replaced (e.g. a relative one). Outside the browser, of `url`.
+pub fn local_location(url: &str) -> Option<IgnoreReason> {
+    if is_browser() {
+        local_page(&window().location().href().unwrap_or_default())
+    } else {
+        local_page(url)
+    }
+}
+
+/// Same exclusions as the official script, based on the page url.
+pub fn local_page(url: &str) -> Option<IgnoreReason> {
+    let (scheme, rest) = url.split_once(':')?;
+    if scheme.eq_ignore_ascii_case("file") {
+        return Some(IgnoreReason::FileProtocol);
+    }
+
+    let authority = rest.trim_start_matches('/').split(['/', '?', '#']).next()?;
+    // Drop user info and port
+    let host_port = authority.rsplit('@').next()?;
+    let host = if host_port.starts_with('[') {
+        host_port.split_inclusive(']').next()?
+    } else {
+        host_port.split(':').next()?
+    }
+    .to_ascii_lowercase();
+
+    let is_local = matches!(host.as_str(), "localhost" | "127.0.0.1" | "[::1]")
+        || host.strip_suffix(".local").is_some();
+    is_local.then_some(IgnoreReason::Localhost)
+}
+
+/// `localStorage` of the browser. Always `None` outside the browser.
+pub fn local_storage() -> Option<Storage> {
+    if is_browser() {
+        window().local_storage().ok().flatten()
+    } else {
+        None
+    }
+}
+
+/// Whether `localStorage.plausible_ignore` is `"true"`. Always `false` outside the browser.
+#[must_use]
+pub fn stored_opt_out() -> bool {
+    local_storage()
+        .and_then(|s| s.get_item(OPT_OUT_KEY).ok().flatten())
+        .is_some_and(|v| v == "true")
+}
+
+pub fn store_opt_out(opted_out: bool) {
+    if let Some(storage) = local_storage() {
+        let _ = if opted_out {
+            storage.set_item(OPT_OUT_KEY, "true")
+        } else {
+            storage.remove_item(OPT_OUT_KEY)
+        };
+    }
+}
+
+/// Global Privacy Control or Do Not Track signal sent by the browser, if any.
+/// Always `None` outside the browser.
+#[must_use]
+pub fn privacy_signal() -> Option<IgnoreReason> {
+    if !is_browser() {
+        return None;
+    }
+    let navigator = window().navigator();
+    // Not exposed by web-sys
+    let gpc = Reflect::get(&navigator, &"globalPrivacyControl".into())
+        .ok()
+        .and_then(|v| v.as_bool());
+    if gpc == Some(true) {
+        return Some(IgnoreReason::GlobalPrivacyControl);
+    }
+    let dnt = Reflect::get(&navigator, &"doNotTrack".into())
+        .ok()
+        .and_then(|v| v.as_string());
+    matches!(dnt.as_deref(), Some("1" | "yes")).then_some(IgnoreReason::DoNotTrack)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn local_pages_are_ignored() {
+        for url in [
+            "http://localhost:3000/",
+            "http://LOCALHOST/path",
+            "https://127.0.0.1/",
+            "http://[::1]:8080/?q=1",
+            "http://user@myhost.local/",
+        ] {
+            assert_eq!(local_page(url), Some(IgnoreReason::Localhost), "{url}");
+        }
+        assert_eq!(
+            local_page("file:///home/index.html"),
+            Some(IgnoreReason::FileProtocol)
+        );
+    }
+
+    #[test]
+    fn public_pages_are_sent() {
+        for url in [
+            "https://example.com/",
+            "https://localhost.example.com/",
+            "https://example.com/localhost",
+            "https://local.example.com:8443/#x",
+            "https://[2001:db8::1]/",
+        ] {
+            assert_eq!(local_page(url), None, "{url}");
+        }
+    }
+}
@@ -0,0 +1,205 @@
+//! First party proxy so events are sent to your own origin instead of the
+//! plausible instance, which ad blockers know about (`axum` and `actix` features).
+//!
+//! Set the same path in [`PlausibleConfig::proxy`] and mount the route in
+//! your server:
+//!
+//! ```ignore
+//! let config = PlausibleConfig::new("your_domain").proxy(DEFAULT_PROXY_PATH);
+//!
+//! // axum
+//! let app = Router::new()
+//!     .leptos_routes(&leptos_options, routes, App)
+//!     .merge(leptos_plausible::proxy::axum_router(&config));
+//!
+//! // actix
+//! App::new().service(leptos_plausible::proxy::actix_service(&config));
+//! ```
+//!
+//! Bodies are forwarded untouched along with the visitor's `User-Agent` and IP.
+//! Only events for the configured domain are accepted so it can't be used to
+//! send events for other sites.
+use crate::{PlausibleConfig, PlausibleError, PlausibleHeader};
+
+/// Path used by the routes when [`PlausibleConfig::proxy_path`] is not set
+pub const DEFAULT_PROXY_PATH: &str = "/_p/event";
+
+/// Forwards event bodies to the instance
+#[derive(Clone, Debug)]
+pub struct Proxy {
+    client: reqwest::Client,
+    endpoint: String,
+    domain: String,
+}
+
+impl Proxy {
+    #[must_use]
+    pub fn new(config: &PlausibleConfig) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            endpoint: config.instance_endpoint(),
+            domain: config.domain.clone(),
+        }
+    }
+
+    #[must_use]
+    pub fn with_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Path where the routes are mounted
+    #[must_use]
+    pub fn path(config: &PlausibleConfig) -> String {
+        config
+            .proxy_path
+            .clone()
+            .unwrap_or_else(|| DEFAULT_PROXY_PATH.into())
+    }
+
+    /// Sends `body` to the instance on behalf of the visitor and returns the
+    /// status code it answered with.
+    ///
+    /// # Errors
+    ///
+    /// [`PlausibleError::Request`] if the body is not an event for the configured
+    /// domain, or [`PlausibleError::Network`] if the instance can't be reached.
+    pub async fn forward(
+        &self,
+        body: Vec<u8>,
+        header: &PlausibleHeader,
+    ) -> Result<u16, PlausibleError> {
+        self.check_domain(&body)?;
+
+        let mut request = self
+            .client
+            .post(&self.endpoint)
+            .header("Content-Type", "application/json")
+            .body(body);
+        if !header.user_agent.is_empty() {
+            request = request.header("User-Agent", &header.user_agent);
+        }
+        if let Some(ip) = &header.x_forwarded_for {
+            request = request.header("X-Forwarded-For", ip);
+        }
+
+        let resp = request
+            .send()
+            .await
+            .map_err(|e| PlausibleError::Network(e.to_string()))?;
+        Ok(resp.status().as_u16())
+    }
+
+    fn check_domain(&self, body: &[u8]) -> Result<(), PlausibleError> {
+        let event: serde_json::Value =
+            serde_json::from_slice(body).map_err(|e| PlausibleError::Request(e.to_string()))?;
+        // The official script uses `d` instead of `domain`
+        let domain = event
+            .get("domain")
+            .or_else(|| event.get("d"))
+            .and_then(serde_json::Value::as_str);
+        if domain == Some(self.domain.as_str()) {
+            Ok(())
+        } else {
+            Err(PlausibleError::Request(format!(
+                "unexpected domain {domain:?}"
+            )))
+        }
+    }
+
+    /// Status the proxy answers with when forwarding failed
+    const fn error_status(e: &PlausibleError) -> u16 {
+        match e {
+            PlausibleError::Network(_) => 502,
+            _ => 400,
+        }
+    }
+}
+
+/// Router serving the proxy at [`Proxy::path`] to be merged with your app.
+#[cfg(feature = "axum")]
+pub fn axum_router<S>(config: &PlausibleConfig) -> axum::Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    use axum::body::Bytes;
+    use axum::extract::ConnectInfo;
+    use axum::http::{HeaderMap, StatusCode};
+    use std::net::SocketAddr;
+
+    let proxy = Proxy::new(config);
+    axum::Router::new().route(
+        &Proxy::path(config),
+        axum::routing::post(
+            move |connect_info: Option<ConnectInfo<SocketAddr>>,
+                  headers: HeaderMap,
+                  body: Bytes| async move {
+                let mut header = PlausibleHeader::from_headers(&headers);
+                if header.x_forwarded_for.is_none() {
+                    header.x_forwarded_for =
+                        connect_info.map(|ConnectInfo(addr)| addr.ip().to_string());
+                }
+                let status = proxy
+                    .forward(body.to_vec(), &header)
+                    .await
+                    .unwrap_or_else(|e| Proxy::error_status(&e));
+                StatusCode::from_u16(status).unwrap_or(StatusCode::BAD_GATEWAY)
+            },
+        ),
+    )
+}
+
+/// Resource serving the proxy at [`Proxy::path`] to be added to your `App`.
+#[cfg(feature = "actix")]
+#[must_use]
+pub fn actix_service(config: &PlausibleConfig) -> actix_web::Resource {
+    use actix_web::http::header::USER_AGENT;
+    use actix_web::http::StatusCode;
+    use actix_web::web::{self, Bytes};
+    use actix_web::{HttpRequest, HttpResponse};
+
+    let proxy = Proxy::new(config);
+    web::resource(Proxy::path(config)).route(web::post().to(
+        move |req: HttpRequest, body: Bytes| {
+            let proxy = proxy.clone();
+            async move {
+                let header = PlausibleHeader {
+                    user_agent: req
+                        .headers()
+                        .get(USER_AGENT)
+                        .and_then(|v| v.to_str().ok())
+                        .unwrap_or_default()
+                        .into(),
+                    // Uses `Forwarded` or `X-Forwarded-For` before the peer address
+                    x_forwarded_for: req.connection_info().realip_remote_addr().map(String::from),
+                };
+                let status = proxy
+                    .forward(body.to_vec(), &header)
+                    .await
+                    .unwrap_or_else(|e| Proxy::error_status(&e));
+                HttpResponse::new(StatusCode::from_u16(status).unwrap_or(StatusCode::BAD_GATEWAY))
+            }
+        },
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn only_forwards_own_domain() {
+        let proxy = Proxy::new(&PlausibleConfig::new("example.com"));
+
+        assert!(proxy
+            .check_domain(br#"{"name":"pageview","domain":"example.com"}"#)
+            .is_ok());
+        assert!(proxy
+            .check_domain(br#"{"n":"pageview","d":"example.com"}"#)
+            .is_ok());
+        assert!(proxy
+            .check_domain(br#"{"name":"pageview","domain":"other.com"}"#)
+            .is_err());
+        assert!(proxy.check_domain(b"not json").is_err());
+    }
+}
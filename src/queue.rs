@@ -0,0 +1,328 @@
+//! Keeps events that could not be sent, e.g. while offline, in `localStorage`
+//! and retries them later with exponential backoff.
+//!
+//! Enable it with [`PlausibleConfig::queue`](crate::PlausibleConfig::queue).
+//! Queued events are retried when the browser goes back online and on the
+//! next page load. The events API has no timestamp, so entries older than
+//! [`QueueConfig::max_age_secs`] are dropped instead of being counted now.
+//!
+//! The queue is dropped instead of retried once the visitor opts out, or when
+//! tracking is disabled or refused with a privacy signal.
+use std::cell::{Cell, RefCell};
+use std::sync::Arc;
+use std::time::Duration;
+
+use leptos::logging::debug_warn;
+use leptos::prelude::{set_timeout, window, window_event_listener_untyped};
+use leptos::task::spawn_local;
+use serde::{Deserialize, Serialize};
+
+use crate::privacy::{local_storage, stored_opt_out};
+use crate::transport::Transport;
+use crate::{is_browser, PlausibleHeader, PlausiblePayload};
+
+/// `localStorage` key where the queue is stored
+pub const QUEUE_KEY: &str = "plausible_queue";
+
+/// Settings of the offline queue
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+#[allow(clippy::module_name_repetitions)]
+pub struct QueueConfig {
+    /// Maximum number of events kept. The oldest are dropped first.
+    pub max_entries: usize,
+    /// Events older than this are dropped
+    pub max_age_secs: u64,
+    /// Delay before the first retry. It doubles after each failed attempt.
+    pub base_delay_ms: u64,
+    /// Maximum delay between retries
+    pub max_delay_ms: u64,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            max_entries: 50,
+            max_age_secs: 60 * 60,
+            base_delay_ms: 1_000,
+            max_delay_ms: 5 * 60 * 1_000,
+        }
+    }
+}
+
+impl QueueConfig {
+    /// Delay before retrying after `attempts` failed attempts
+    #[must_use]
+    pub fn backoff(&self, attempts: u32) -> Duration {
+        let delay = self
+            .base_delay_ms
+            .saturating_mul(2_u64.saturating_pow(attempts.saturating_sub(1)));
+        Duration::from_millis(delay.min(self.max_delay_ms))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct QueuedEvent {
+    endpoint: String,
+    payload: PlausiblePayload,
+    /// Milliseconds since the epoch
+    queued_at: f64,
+    attempts: u32,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Queue(Vec<QueuedEvent>);
+
+impl Queue {
+    fn push(&mut self, event: QueuedEvent, config: &QueueConfig) {
+        self.0.push(event);
+        let overflow = self.0.len().saturating_sub(config.max_entries);
+        self.0.drain(..overflow);
+    }
+
+    /// Drops the events older than the max age
+    #[allow(clippy::cast_precision_loss)]
+    fn prune(&mut self, now: f64, config: &QueueConfig) {
+        let max_age_ms = config.max_age_secs as f64 * 1_000.0;
+        self.0.retain(|e| now - e.queued_at <= max_age_ms);
+    }
+
+    fn load() -> Self {
+        local_storage()
+            .and_then(|s| s.get_item(QUEUE_KEY).ok().flatten())
+            .and_then(|q| serde_json::from_str(&q).ok())
+            .unwrap_or_default()
+    }
+
+    fn store(&self) {
+        let Some(storage) = local_storage() else {
+            return;
+        };
+        if self.0.is_empty() {
+            let _ = storage.remove_item(QUEUE_KEY);
+        } else if let Ok(q) = serde_json::to_string(self) {
+            let _ = storage.set_item(QUEUE_KEY, &q);
+        }
+    }
+}
+
+/// Whether the browser says it's offline. Always `false` outside the browser.
+#[must_use]
+pub fn is_offline() -> bool {
+    is_browser() && !window().navigator().on_line()
+}
+
+/// Stores an event that could not be sent to retry it later
+pub fn push(endpoint: String, payload: PlausiblePayload, config: &QueueConfig) {
+    let event = QueuedEvent {
+        endpoint,
+        payload,
+        queued_at: js_sys::Date::now(),
+        attempts: 0,
+    };
+    with_queue(|queue| queue.push(event, config));
+}
+
+thread_local! {
+    static FLUSHING: Cell<bool> = const { Cell::new(false) };
+    static LISTENING: Cell<bool> = const { Cell::new(false) };
+    /// Transport and settings of the last [`start`]
+    static CURRENT: RefCell<Option<(Arc<dyn Transport>, QueueConfig)>> = const { RefCell::new(None) };
+    /// Loaded from `localStorage` once, so events pushed while a [`flush`] is
+    /// waiting for the transport are not overwritten by it
+    static QUEUE: RefCell<Option<Queue>> = const { RefCell::new(None) };
+}
+
+/// Drops the queued events, e.g. when the visitor opts out
+pub fn clear() {
+    with_queue(|queue| queue.0.clear());
+}
+
+/// Updates the queue and stores it right away, in case the page is closed
+fn with_queue<R>(update: impl FnOnce(&mut Queue) -> R) -> R {
+    QUEUE.with(|q| {
+        let mut q = q.borrow_mut();
+        let queue = q.get_or_insert_with(Queue::load);
+        let res = update(queue);
+        queue.store();
+        res
+    })
+}
+
+/// Retries the queued events now and every time the browser goes back online.
+///
+/// The listener is installed once, later calls (e.g. a remounted provider)
+/// replace the transport and settings it uses.
+pub fn start(transport: Arc<dyn Transport>, config: QueueConfig) {
+    CURRENT.with(|c| *c.borrow_mut() = Some((Arc::clone(&transport), config.clone())));
+    if !LISTENING.with(|l| l.replace(true)) {
+        let online = |_| {
+            if let Some((transport, config)) = CURRENT.with(|c| c.borrow().clone()) {
+                flush(transport, config);
+            }
+        };
+        // Kept for the whole life of the page
+        std::mem::forget(window_event_listener_untyped("online", online));
+    }
+    flush(transport, config);
+}
+
+/// Sends the queued events in order. On failure the rest are kept and a new
+/// attempt is scheduled with exponential backoff.
+pub fn flush(transport: Arc<dyn Transport>, config: QueueConfig) {
+    if FLUSHING.with(|f| f.replace(true)) {
+        return;
+    }
+    spawn_local(async move {
+        let retry = send_queued(transport.as_ref(), &config, js_sys::Date::now()).await;
+        FLUSHING.with(|f| f.set(false));
+
+        if let Some(delay) = retry {
+            set_timeout(move || flush(transport, config), delay);
+        }
+    });
+}
+
+/// Sends the queued events until one fails, returning the delay before the
+/// next attempt
+#[allow(clippy::future_not_send)]
+async fn send_queued(
+    transport: &dyn Transport,
+    config: &QueueConfig,
+    now: f64,
+) -> Option<Duration> {
+    // Opted out since they were queued, e.g. in another tab
+    if stored_opt_out() {
+        clear();
+        return None;
+    }
+    with_queue(|queue| queue.prune(now, config));
+
+    let header = PlausibleHeader::default();
+    // Cloned, other events may be queued while it's being sent
+    while let Some(event) = with_queue(|queue| queue.0.first().cloned()) {
+        if is_offline() {
+            return None;
+        }
+        let res = transport
+            .send(&event.endpoint, &event.payload, &header)
+            .await;
+        let retry = with_queue(|queue| {
+            // It may have been dropped meanwhile by `max_entries`
+            let i = queue.0.iter().position(|e| *e == event)?;
+            match res {
+                Err(e) if e.is_retryable() => {
+                    let queued = &mut queue.0[i];
+                    queued.attempts += 1;
+                    debug_warn!(
+                        "Plausible queued event not sent ({e}), retry #{}",
+                        queued.attempts
+                    );
+                    Some(config.backoff(queued.attempts))
+                }
+                // Sent, or it will never be accepted
+                _ => {
+                    queue.0.remove(i);
+                    None
+                }
+            }
+        });
+        if retry.is_some() {
+            return retry;
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transport::TransportFuture;
+    use crate::PlausibleError;
+
+    /// Instance that is down, where event `a` fails while `b` is queued
+    #[derive(Debug)]
+    struct DownTransport;
+
+    impl Transport for DownTransport {
+        fn send<'a>(
+            &'a self,
+            _endpoint: &'a str,
+            payload: &'a PlausiblePayload,
+            _header: &'a PlausibleHeader,
+        ) -> TransportFuture<'a> {
+            Box::pin(async move {
+                if payload.name == "a" {
+                    with_queue(|q| q.push(event("b", 1.0), &QueueConfig::default()));
+                }
+                Err(PlausibleError::Network("instance down".into()))
+            })
+        }
+    }
+
+    fn event(name: &str, queued_at: f64) -> QueuedEvent {
+        QueuedEvent {
+            endpoint: "/api/event".into(),
+            payload: PlausiblePayload::test(name, "https://example.com/"),
+            queued_at,
+            attempts: 0,
+        }
+    }
+
+    #[test]
+    fn keeps_newest_entries() {
+        let config = QueueConfig {
+            max_entries: 2,
+            ..QueueConfig::default()
+        };
+        let mut queue = Queue::default();
+        for (i, name) in ["a", "b", "c"].into_iter().enumerate() {
+            queue.push(
+                event(name, f64::from(u8::try_from(i).expect("small"))),
+                &config,
+            );
+        }
+        let names: Vec<_> = queue.0.iter().map(|e| e.payload.name.as_str()).collect();
+        assert_eq!(names, ["b", "c"]);
+    }
+
+    #[test]
+    fn drops_stale_entries() {
+        let config = QueueConfig {
+            max_age_secs: 10,
+            ..QueueConfig::default()
+        };
+        let mut queue = Queue(vec![event("old", 0.0), event("new", 15_000.0)]);
+        queue.prune(20_000.0, &config);
+        assert_eq!(queue.0.len(), 1);
+        assert_eq!(queue.0[0].payload.name, "new");
+    }
+
+    #[test]
+    fn exponential_backoff() {
+        let config = QueueConfig {
+            base_delay_ms: 100,
+            max_delay_ms: 1_000,
+            ..QueueConfig::default()
+        };
+        assert_eq!(config.backoff(1), Duration::from_millis(100));
+        assert_eq!(config.backoff(2), Duration::from_millis(200));
+        assert_eq!(config.backoff(4), Duration::from_millis(800));
+        assert_eq!(config.backoff(5), Duration::from_secs(1));
+        assert_eq!(config.backoff(100), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn keeps_events_queued_while_flushing() {
+        let config = QueueConfig::default();
+        with_queue(|q| q.push(event("a", 0.0), &config));
+
+        let retry = futures::executor::block_on(send_queued(&DownTransport, &config, 0.0));
+
+        assert_eq!(retry, Some(config.backoff(1)));
+        let queue = with_queue(|q| q.0.clone());
+        let names: Vec<_> = queue.iter().map(|e| e.payload.name.as_str()).collect();
+        assert_eq!(names, ["a", "b"]);
+        assert_eq!(queue[0].attempts, 1);
+    }
+}
@@ -0,0 +1,330 @@
+//! Sending events from the server (`ssr` feature).
+//!
+//! During server side rendering there is no `window`, so the page and the
+//! visitor are taken from the incoming request that `leptos_axum` provides as
+//! [`http::request::Parts`] context (or `leptos_actix` as `leptos_actix::Request`), and
+//! events are posted with [`reqwest`].
+//!
+//! The visitor's `User-Agent` and IP (as `X-Forwarded-For`) are forwarded so
+//! plausible counts the visitor and not the server. Without a reverse proxy
+//! the IP is the address of the connection: axum's `ConnectInfo` (see
+//! `into_make_service_with_connect_info`) or actix's `peer_addr`.
+//!
+//! Outside of a request, e.g. on webhooks, use
+//! [`Plausible::event_with_context`](crate::Plausible::event_with_context), or
+//! [`EventBuilder::context`](crate::EventBuilder::context) for the built-in events.
+use http::header::{HOST, REFERER, USER_AGENT};
+use http::request::Parts;
+use http::HeaderMap;
+use leptos::logging::debug_warn;
+use leptos::prelude::use_context;
+use std::sync::OnceLock;
+
+use crate::event::{PageContext, PlausibleHeader, PlausiblePayload};
+use crate::transport::{Transport, TransportFuture, TransportResult};
+use crate::{PlausibleError, SendOutcome};
+
+/// Sends events from the server with [`reqwest`].
+///
+/// It's the default transport outside the browser when the `ssr` feature is enabled.
+/// By default all of them share one client, and so its connection pool, since
+/// one is created for every request being rendered.
+#[derive(Clone, Debug)]
+pub struct ReqwestTransport {
+    client: reqwest::Client,
+}
+
+impl Default for ReqwestTransport {
+    fn default() -> Self {
+        static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+        Self::new(CLIENT.get_or_init(reqwest::Client::new).clone())
+    }
+}
+
+impl ReqwestTransport {
+    #[must_use]
+    pub const fn new(client: reqwest::Client) -> Self {
+        Self { client }
+    }
+}
+
+impl Transport for ReqwestTransport {
+    fn send<'a>(
+        &'a self,
+        endpoint: &'a str,
+        payload: &'a PlausiblePayload,
+        header: &'a PlausibleHeader,
+    ) -> TransportFuture<'a> {
+        Box::pin(post(&self.client, endpoint, payload, header))
+    }
+
+    /// There is no page to unload in the server, so it's just sent in the
+    /// background with `tokio::spawn`.
+    ///
+    /// # Errors
+    ///
+    /// [`PlausibleError::Request`] outside of a tokio runtime
+    fn send_beacon(
+        &self,
+        endpoint: &str,
+        payload: &PlausiblePayload,
+        header: &PlausibleHeader,
+    ) -> TransportResult {
+        let runtime = tokio::runtime::Handle::try_current()
+            .map_err(|e| PlausibleError::Request(e.to_string()))?;
+        let client = self.client.clone();
+        let endpoint = endpoint.to_owned();
+        let payload = payload.clone();
+        let header = header.clone();
+        runtime.spawn(async move {
+            if let Err(e) = post(&client, &endpoint, &payload, &header).await {
+                debug_warn!("Plausible event `{}` not sent: {e}", payload.name);
+            }
+        });
+        Ok(SendOutcome::Opaque)
+    }
+}
+
+async fn post(
+    client: &reqwest::Client,
+    endpoint: &str,
+    payload: &PlausiblePayload,
+    header: &PlausibleHeader,
+) -> TransportResult {
+    let body =
+        serde_json::to_string(payload).map_err(|e| PlausibleError::Serialization(e.to_string()))?;
+
+    let mut request = client
+        .post(endpoint)
+        .header("Content-Type", "application/json")
+        .body(body);
+    if !header.user_agent.is_empty() {
+        request = request.header(USER_AGENT, &header.user_agent);
+    }
+    if let Some(ip) = &header.x_forwarded_for {
+        request = request.header("X-Forwarded-For", ip);
+    }
+
+    let resp = request.send().await.map_err(|e| {
+        if e.is_builder() {
+            PlausibleError::Request(e.to_string())
+        } else {
+            PlausibleError::Network(e.to_string())
+        }
+    })?;
+
+    match resp.status().as_u16() {
+        202 => Ok(SendOutcome::Accepted),
+        status => Err(PlausibleError::Status(status)),
+    }
+}
+
+impl PlausibleHeader {
+    /// Visitor's `User-Agent` and IP from the headers of their request
+    #[must_use]
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        Self {
+            user_agent: header_value(headers, USER_AGENT.as_str()).unwrap_or_default(),
+            x_forwarded_for: client_ip(headers),
+        }
+    }
+}
+
+impl PageContext {
+    /// Builds the context of the request being served, taken from the
+    /// [`http::request::Parts`] context provided by `leptos_axum` or the
+    /// `leptos_actix::Request` one provided by `leptos_actix`.
+    #[must_use]
+    pub fn from_request() -> Option<Self> {
+        #[cfg(feature = "actix")]
+        if let Some(req) = use_context::<leptos_actix::Request>() {
+            return Some(Self::from_actix(&req));
+        }
+        use_context::<Parts>().map(|parts| Self::from_parts(&parts))
+    }
+
+    #[cfg(feature = "actix")]
+    #[must_use]
+    pub fn from_actix(req: &actix_web::HttpRequest) -> Self {
+        let info = req.connection_info();
+        let mut builder = http::Request::builder().uri(format!(
+            "{}://{}{}",
+            info.scheme(),
+            info.host(),
+            req.uri().path_and_query().map_or("/", |p| p.as_str())
+        ));
+        for (name, value) in req.headers() {
+            builder = builder.header(name.as_str(), value.as_bytes());
+        }
+        let mut ctx = builder
+            .body(())
+            .map_or_else(|_| Self::default(), |r| Self::from_parts(&r.into_parts().0));
+        if ctx.header.x_forwarded_for.is_none() {
+            ctx.header.x_forwarded_for = req.peer_addr().map(|addr| addr.ip().to_string());
+        }
+        ctx
+    }
+
+    #[must_use]
+    pub fn from_parts(parts: &Parts) -> Self {
+        let headers = &parts.headers;
+        let scheme = parts
+            .uri
+            .scheme_str()
+            .map(String::from)
+            .or_else(|| header_value(headers, "x-forwarded-proto"))
+            .unwrap_or_else(|| "https".into());
+        let host = parts
+            .uri
+            .host()
+            .map(String::from)
+            .or_else(|| header_value(headers, "x-forwarded-host"))
+            .or_else(|| header_value(headers, HOST.as_str()))
+            .unwrap_or_default();
+        let path = parts
+            .uri
+            .path_and_query()
+            .map_or("/", http::uri::PathAndQuery::as_str);
+
+        let mut header = PlausibleHeader::from_headers(headers);
+        if header.x_forwarded_for.is_none() {
+            header.x_forwarded_for = peer_ip(parts);
+        }
+
+        Self {
+            url: format!("{scheme}://{host}{path}"),
+            referrer: header_value(headers, REFERER.as_str()),
+            header,
+        }
+    }
+}
+
+/// Address of the connection, when axum serves with `ConnectInfo`
+#[allow(unused_variables, clippy::missing_const_for_fn)]
+fn peer_ip(parts: &Parts) -> Option<String> {
+    #[cfg(feature = "axum")]
+    if let Some(info) = parts
+        .extensions
+        .get::<axum::extract::ConnectInfo<std::net::SocketAddr>>()
+    {
+        return Some(info.0.ip().to_string());
+    }
+    None
+}
+
+fn header_value(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(String::from)
+}
+
+/// Original client IP when behind proxies, from `X-Forwarded-For`,
+/// `X-Real-IP` or `Forwarded`
+fn client_ip(headers: &HeaderMap) -> Option<String> {
+    let first = |v: String| v.split(',').next().map(|ip| ip.trim().to_owned());
+
+    header_value(headers, "x-forwarded-for")
+        .and_then(first)
+        .or_else(|| header_value(headers, "x-real-ip"))
+        .or_else(|| {
+            header_value(headers, "forwarded")
+                .and_then(first)?
+                .split(';')
+                .find_map(|pair| {
+                    let (key, value) = pair.trim().split_once('=')?;
+                    key.eq_ignore_ascii_case("for")
+                        .then(|| value.trim_matches('"').to_owned())
+                })
+        })
+        .filter(|ip| !ip.is_empty())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parts(headers: &[(&str, &str)]) -> Parts {
+        let mut builder = http::Request::builder().uri("/blog/post?utm_source=x");
+        for (k, v) in headers {
+            builder = builder.header(*k, *v);
+        }
+        builder.body(()).expect("request").into_parts().0
+    }
+
+    #[test]
+    fn context_from_request() {
+        let ctx = PageContext::from_parts(&parts(&[
+            ("host", "example.com"),
+            ("user-agent", "Mozilla/5.0"),
+            ("referer", "https://duckduckgo.com/"),
+            ("x-forwarded-for", "203.0.113.7, 10.0.0.1"),
+        ]));
+
+        assert_eq!(ctx.url, "https://example.com/blog/post?utm_source=x");
+        assert_eq!(ctx.referrer.as_deref(), Some("https://duckduckgo.com/"));
+        assert_eq!(ctx.header.user_agent, "Mozilla/5.0");
+        assert_eq!(ctx.header.x_forwarded_for.as_deref(), Some("203.0.113.7"));
+    }
+
+    #[test]
+    fn client_ip_fallbacks() {
+        let ip = |headers| client_ip(&parts(headers).headers);
+
+        assert_eq!(
+            ip(&[("x-real-ip", "203.0.113.8")]).as_deref(),
+            Some("203.0.113.8")
+        );
+        assert_eq!(
+            ip(&[(
+                "forwarded",
+                "for=\"[2001:db8::1]\";proto=https, for=10.0.0.1"
+            )])
+            .as_deref(),
+            Some("[2001:db8::1]")
+        );
+        assert_eq!(ip(&[]), None);
+    }
+
+    #[test]
+    fn beacon_needs_a_runtime() {
+        let res = ReqwestTransport::default().send_beacon(
+            "http://localhost/api/event",
+            &PlausiblePayload::test("Signup", "https://example.com/"),
+            &PlausibleHeader::default(),
+        );
+        assert!(matches!(res, Err(PlausibleError::Request(_))));
+    }
+
+    #[cfg(feature = "axum")]
+    #[test]
+    fn connection_ip() {
+        let mut request = parts(&[("host", "example.com")]);
+        request
+            .extensions
+            .insert(axum::extract::ConnectInfo(std::net::SocketAddr::from((
+                [203, 0, 113, 9],
+                4000,
+            ))));
+        let ctx = PageContext::from_parts(&request);
+        assert_eq!(ctx.header.x_forwarded_for.as_deref(), Some("203.0.113.9"));
+    }
+
+    #[cfg(feature = "actix")]
+    #[test]
+    fn context_from_actix() {
+        let req = actix_web::test::TestRequest::get()
+            .uri("/blog/post?utm_source=x")
+            .insert_header(("host", "example.com"))
+            .insert_header(("user-agent", "Mozilla/5.0"))
+            .peer_addr(std::net::SocketAddr::from(([203, 0, 113, 10], 4000)))
+            .to_http_request();
+        let ctx = PageContext::from_actix(&req);
+
+        assert_eq!(ctx.url, "http://example.com/blog/post?utm_source=x");
+        assert_eq!(ctx.header.user_agent, "Mozilla/5.0");
+        assert_eq!(ctx.header.x_forwarded_for.as_deref(), Some("203.0.113.10"));
+    }
+}
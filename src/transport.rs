@@ -0,0 +1,248 @@
+//! How events reach the plausible instance.
+//!
+//! By default events are posted with the browser `fetch` API ([`FetchTransport`]),
+//! but any [`Transport`] can be set with [`Plausible::with_transport`](crate::Plausible::with_transport).
+//!
+//! ```
+//! # use leptos_plausible::Plausible;
+//! # use leptos_plausible::transport::RecordingTransport;
+//! let recorder = RecordingTransport::default();
+//! let p = Plausible::new("your_domain").with_transport(recorder.clone());
+//! ```
+//!
+//! Pass it to [`provide_plausible_context_with`](crate::components::provide_plausible_context_with)
+//! to use it in the whole app.
+use std::fmt::Debug;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use gloo_net::http::Request;
+use js_sys::Reflect;
+use leptos::prelude::window;
+use wasm_bindgen::JsValue;
+use web_sys::{RequestInit, RequestMode};
+
+use crate::event::{PlausibleHeader, PlausiblePayload};
+use crate::{is_browser, PlausibleError, SendOutcome};
+
+pub type TransportResult = Result<SendOutcome, PlausibleError>;
+
+/// Future returned by [`Transport::send`]
+pub type TransportFuture<'a> = Pin<Box<dyn Future<Output = TransportResult> + 'a>>;
+
+/// Delivers a single event to `endpoint`.
+///
+/// It's `Send + Sync` since the [`Plausible`](crate::Plausible) holding it
+/// is provided as leptos context, but the futures it returns don't need to be.
+pub trait Transport: Debug + Send + Sync {
+    fn send<'a>(
+        &'a self,
+        endpoint: &'a str,
+        payload: &'a PlausiblePayload,
+        header: &'a PlausibleHeader,
+    ) -> TransportFuture<'a>;
+
+    /// Hands the event to the browser so it's delivered even if the page is
+    /// unloaded right after. Defaults to [`beacon`].
+    ///
+    /// # Errors
+    ///
+    /// See [`PlausibleError`]
+    fn send_beacon(
+        &self,
+        endpoint: &str,
+        payload: &PlausiblePayload,
+        _header: &PlausibleHeader,
+    ) -> TransportResult {
+        beacon(endpoint, payload)
+    }
+}
+
+/// [`FetchTransport`] in the browser, [`ReqwestTransport`](crate::server::ReqwestTransport)
+/// in the server with the `ssr` feature
+pub(crate) fn default_transport() -> Arc<dyn Transport> {
+    #[cfg(feature = "ssr")]
+    if !is_browser() {
+        return Arc::new(crate::server::ReqwestTransport::default());
+    }
+    Arc::new(FetchTransport)
+}
+
+/// Sends the event with `navigator.sendBeacon`, falling back to a `fetch` with
+/// `keepalive` if the beacon is rejected. Both survive navigation.
+///
+/// The response can't be read so it always returns [`SendOutcome::Opaque`].
+///
+/// # Errors
+///
+/// [`PlausibleError::Request`] outside the browser, see [`PlausibleError`]
+pub fn beacon(endpoint: &str, payload: &PlausiblePayload) -> TransportResult {
+    if !is_browser() {
+        return Err(PlausibleError::Request(
+            "beacons can only be sent from the browser".into(),
+        ));
+    }
+    let body =
+        serde_json::to_string(payload).map_err(|e| PlausibleError::Serialization(e.to_string()))?;
+    let window = window();
+
+    if window
+        .navigator()
+        .send_beacon_with_opt_str(endpoint, Some(&body))
+        .unwrap_or(false)
+    {
+        return Ok(SendOutcome::Opaque);
+    }
+
+    let init = RequestInit::new();
+    init.set_method("POST");
+    init.set_mode(RequestMode::Cors);
+    init.set_body(&JsValue::from_str(&body));
+    // `keepalive` is not exposed by web-sys
+    Reflect::set(&init, &"keepalive".into(), &JsValue::TRUE)
+        .map_err(|e| PlausibleError::Request(format!("{e:?}")))?;
+    // The request is started right away, there is nothing to wait for
+    let _ = window.fetch_with_str_and_init(endpoint, &init);
+    Ok(SendOutcome::Opaque)
+}
+
+/// Default transport using `fetch` through [`gloo_net`]
+///
+/// Like the official script it sends the body as `text/plain` so no CORS
+/// preflight is needed and the response status can still be read.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FetchTransport;
+
+impl Transport for FetchTransport {
+    fn send<'a>(
+        &'a self,
+        endpoint: &'a str,
+        payload: &'a PlausiblePayload,
+        _header: &'a PlausibleHeader,
+    ) -> TransportFuture<'a> {
+        Box::pin(async move {
+            // FIXME this from_serde should work but returns JSValue(Object(...)) which fails
+            //let body = JsValue::from_serde(payload).expect("ERR serializing");
+            let body = serde_json::to_string(payload)
+                .map_err(|e| PlausibleError::Serialization(e.to_string()))?;
+
+            let resp = Request::post(endpoint)
+                .referrer_policy(web_sys::ReferrerPolicy::StrictOriginWhenCrossOrigin)
+                .mode(web_sys::RequestMode::Cors)
+                .header("Content-Type", "text/plain")
+                .body(JsValue::from_str(&body))
+                .map_err(|e| PlausibleError::Request(e.to_string()))?
+                .send()
+                .await
+                .map_err(|e| PlausibleError::Network(e.to_string()))?;
+
+            match resp.status() {
+                202 => Ok(SendOutcome::Accepted),
+                status => Err(PlausibleError::Status(status)),
+            }
+        })
+    }
+}
+
+/// Sends every event with [`beacon`]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BeaconTransport;
+
+impl Transport for BeaconTransport {
+    fn send<'a>(
+        &'a self,
+        endpoint: &'a str,
+        payload: &'a PlausiblePayload,
+        header: &'a PlausibleHeader,
+    ) -> TransportFuture<'a> {
+        let res = self.send_beacon(endpoint, payload, header);
+        Box::pin(async move { res })
+    }
+}
+
+/// Keeps the events in memory instead of sending them.
+///
+/// Clones share the same storage, so keep one to inspect what was sent.
+/// Useful for tests.
+#[derive(Clone, Debug, Default)]
+pub struct RecordingTransport {
+    events: Arc<Mutex<Vec<(String, PlausiblePayload, PlausibleHeader)>>>,
+}
+
+impl RecordingTransport {
+    /// Events recorded so far with the endpoint they were sent to
+    #[must_use]
+    pub fn events(&self) -> Vec<(String, PlausiblePayload, PlausibleHeader)> {
+        self.events
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    pub fn clear(&self) {
+        self.events
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+}
+
+impl Transport for RecordingTransport {
+    fn send<'a>(
+        &'a self,
+        endpoint: &'a str,
+        payload: &'a PlausiblePayload,
+        header: &'a PlausibleHeader,
+    ) -> TransportFuture<'a> {
+        let res = self.send_beacon(endpoint, payload, header);
+        Box::pin(async move { res })
+    }
+
+    fn send_beacon(
+        &self,
+        endpoint: &str,
+        payload: &PlausiblePayload,
+        header: &PlausibleHeader,
+    ) -> TransportResult {
+        self.events
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push((endpoint.into(), payload.clone(), header.clone()));
+        Ok(SendOutcome::Accepted)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn recording_shares_events_between_clones() {
+        let recorder = RecordingTransport::default();
+        let transport: Arc<dyn Transport> = Arc::new(recorder.clone());
+        let payload = PlausiblePayload::test("pageview", "https://example.com/");
+        let header = PlausibleHeader::new("agent".into(), Some("127.0.0.1".into()));
+
+        let res = futures::executor::block_on(transport.send("/api/event", &payload, &header));
+
+        assert_eq!(res, Ok(SendOutcome::Accepted));
+        let events = recorder.events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].0, "/api/event");
+        assert_eq!(events[0].1.name, "pageview");
+        recorder.clear();
+        assert!(recorder.events().is_empty());
+    }
+
+    #[test]
+    fn beacon_needs_a_browser() {
+        // No `window` to send it from
+        let res = FetchTransport.send_beacon(
+            "/api/event",
+            &PlausiblePayload::test("pageview", "https://example.com/"),
+            &PlausibleHeader::default(),
+        );
+        assert!(matches!(res, Err(PlausibleError::Request(_))));
+    }
+}